@@ -0,0 +1,431 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use futures::StreamExt;
+use renamer::file_ops::display_path;
+use renamer::device::default_devices_file;
+use renamer::journal::default_journal_dir;
+use renamer::{ClockOffset, ClockOffsets, Counter, DeviceProfile, FsProfile, InputMerge, Journal, Normalization, PlanReport, Progress, RenameJob, SortOrder, TagWriting, TransferMode, Transliteration};
+
+const USAGE: &str = "\
+Usage: renamer <command> [options]
+
+Commands:
+  plan     Print the new file names and any conflicts without touching any file
+  apply    Transfer the files into the output folder under their new names
+  undo     Revert the last apply, leaving files that changed since untouched
+  devices  List the device profiles and the file they are kept in
+  help     Show this message
+
+Options:
+  -i, --input <DIR>           Input folder (required for plan and apply; repeat to
+                              number several folders as one sequence)
+      --merge <MODE>          How several input folders are combined: concat,
+                              merge or round-robin (default: concat)
+  -o, --output <DIR>          Output folder (default: <first input>/output)
+  -e, --ext <EXT>             File extensions to pick up, e.g. mp3,flac,m4a (default: mp3)
+      --no-extension          Also pick up files without an extension
+      --include <GLOB>        Only pick up files matching the pattern, e.g. **/Disc */**
+                              (repeatable)
+      --exclude <GLOB>        Skip files matching the pattern, e.g. **/Live/** or *sample*
+                              (repeatable)
+  -p, --padding <N>           Number of leading zeros (default: automatic)
+  -n, --include-original-name Append the original file name after the index
+      --start <N>             First sequence number (default: 1)
+      --step <N>              Increment between sequence numbers, 1 or more (default: 1)
+      --descending            Number from the highest down to the start number
+      --numbering <MODE>      continuous, per-folder or hierarchical (default: continuous)
+  -s, --sort <KEY>            name, path, mtime, ctime, size, date, artist, album,
+                              disc, track or title (default: path)
+      --sort-descending       Reverse the sort order
+      --clock-offset <SPEC>   Shift capture dates of a folder or camera before sorting
+                              and naming, e.g. Phone=+1h or camera:Canon EOS 80D=-3m
+                              (repeatable; the first match applies)
+  -t, --template <TEMPLATE>   Naming template, e.g. {n:03}-{stem}{ext} (overrides -n)
+  -m, --mode <MODE>           copy, move, hardlink, symlink or reflink (default: copy)
+      --preserve-tree         Recreate the input subfolders inside the output folder
+      --translit <SCHEME>     Spell the new names in ASCII: gost (GOST 7.79 system B),
+                              iso9 or phonetic; also covers Greek and accented letters
+      --fs <PROFILE>          Make names safe for the output's file system: fat32,
+                              exfat, ntfs or ext4 (illegal characters, reserved names,
+                              trailing dots, name and path length). Names differing only
+                              in case or Unicode form are conflicts, except on ext4
+      --normalize <FORM>      Unicode form of the new names: nfc, nfd or none (default: none)
+      --write-tags            Write the new number into each copy's track-number tag
+      --write-total           Also write the total number of tracks (implies --write-tags)
+      --write-title           Also write a cleaned-up title (implies --write-tags)
+      --device <NAME>         Fit the output to a device profile (see `renamer devices`):
+                              file system, files per folder, folder depth, ASCII or
+                              8.3 names; sets --fs and, for ASCII devices, --translit
+      --devices-file <FILE>   Where device profiles are kept (default: user config folder)
+      --in-place              Rename files in their own folder instead of copying them
+  -f, --force                 Apply even if the plan has conflicts (overwrites files)
+      --journal-dir <DIR>     Where undo journals are kept (default: user data folder)
+
+Run without a command to open the graphical interface.";
+
+// --- Command-line options ---
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Plan,
+    Apply,
+    Undo,
+    Devices,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub inputs: Vec<PathBuf>,
+    pub input_merge: InputMerge,
+    pub output: Option<PathBuf>,
+    pub extension: String,
+    pub no_extension: bool,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub padding_zeros: Option<usize>,
+    pub include_original_name: bool,
+    pub template: Option<String>,
+    pub counter: Counter,
+    pub sort: SortOrder,
+    pub clock_offsets: Vec<ClockOffset>,
+    pub preserve_tree: bool,
+    pub write_tags: Option<TagWriting>,
+    pub transliteration: Option<Transliteration>,
+    pub fs_profile: Option<FsProfile>,
+    pub normalization: Normalization,
+    pub device: Option<String>,
+    pub devices_file: Option<PathBuf>,
+    pub in_place: bool,
+    pub transfer_mode: TransferMode,
+    pub force: bool,
+    pub journal_dir: Option<PathBuf>,
+}
+
+fn parse_number(raw: &str, what: &str) -> Result<u64, String> {
+    raw.parse::<u64>().map_err(|_| format!("Invalid {}: {}", what, raw))
+}
+
+// Option values other than paths are text; paths are kept as given, even when not UTF-8.
+fn text(raw: OsString) -> Result<String, String> {
+    raw.into_string().map_err(|raw| format!("Not valid UTF-8: {}", raw.to_string_lossy()))
+}
+
+pub fn parse_args(args: &[OsString]) -> Result<Options, String> {
+    let mut iter = args.iter();
+    let command = match iter.next().map(|s| s.to_string_lossy()).as_deref() {
+        Some("plan") => Command::Plan,
+        Some("apply") => Command::Apply,
+        Some("undo") => Command::Undo,
+        Some("devices") => Command::Devices,
+        Some("help") | Some("-h") | Some("--help") => Command::Help,
+        Some(other) => return Err(format!("Unknown command: {}", other)),
+        None => return Err("Missing command.".to_string()),
+    };
+
+    let mut options = Options {
+        command,
+        inputs: Vec::new(),
+        input_merge: InputMerge::Concatenate,
+        output: None,
+        extension: "mp3".to_string(),
+        no_extension: false,
+        include: Vec::new(),
+        exclude: Vec::new(),
+        padding_zeros: None,
+        include_original_name: false,
+        template: None,
+        counter: Counter::default(),
+        sort: SortOrder::default(),
+        clock_offsets: Vec::new(),
+        preserve_tree: false,
+        write_tags: None,
+        transliteration: None,
+        fs_profile: None,
+        normalization: Normalization::Unchanged,
+        device: None,
+        devices_file: None,
+        in_place: false,
+        transfer_mode: TransferMode::Copy,
+        force: false,
+        journal_dir: None,
+    };
+
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_str().ok_or_else(|| format!("Unknown option: {}", arg.to_string_lossy()))?;
+        let mut value = |name: &str| {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", name))
+        };
+        match arg {
+            "-i" | "--input" => options.inputs.push(value(arg)?.into()),
+            "--merge" => options.input_merge = text(value(arg)?)?.parse()?,
+            "-o" | "--output" => options.output = Some(value(arg)?.into()),
+            "-e" | "--ext" => options.extension = text(value(arg)?)?.trim_start_matches('.').to_string(),
+            "--no-extension" => options.no_extension = true,
+            "--include" => options.include.push(text(value(arg)?)?),
+            "--exclude" => options.exclude.push(text(value(arg)?)?),
+            "-p" | "--padding" => {
+                let raw = text(value(arg)?)?;
+                let padding = raw.parse::<usize>()
+                    .map_err(|_| format!("Invalid padding: {}", raw))?;
+                options.padding_zeros = Some(padding);
+            }
+            "-n" | "--include-original-name" => options.include_original_name = true,
+            "-t" | "--template" => options.template = Some(text(value(arg)?)?),
+            "--start" => options.counter.start = parse_number(&text(value(arg)?)?, "start number")?,
+            "--step" => options.counter.step = parse_number(&text(value(arg)?)?, "step")?,
+            "--descending" => options.counter.descending = true,
+            "--numbering" => options.counter.numbering = text(value(arg)?)?.parse()?,
+            "-m" | "--mode" => options.transfer_mode = text(value(arg)?)?.parse()?,
+            "-s" | "--sort" => options.sort.key = text(value(arg)?)?.parse()?,
+            "--sort-descending" => options.sort.descending = true,
+            "--clock-offset" => options.clock_offsets.push(text(value(arg)?)?.parse()?),
+            "--preserve-tree" => options.preserve_tree = true,
+            "--write-tags" => {
+                options.write_tags.get_or_insert_default();
+            }
+            "--write-total" => options.write_tags.get_or_insert_default().total_tracks = true,
+            "--write-title" => options.write_tags.get_or_insert_default().title = true,
+            "--translit" => options.transliteration = Some(text(value(arg)?)?.parse()?),
+            "--fs" => options.fs_profile = Some(text(value(arg)?)?.parse()?),
+            "--normalize" => options.normalization = text(value(arg)?)?.parse()?,
+            "--device" => options.device = Some(text(value(arg)?)?),
+            "--devices-file" => options.devices_file = Some(value(arg)?.into()),
+            "--in-place" => options.in_place = true,
+            "-f" | "--force" => options.force = true,
+            "--journal-dir" => options.journal_dir = Some(value(arg)?.into()),
+            "-h" | "--help" => options.command = Command::Help,
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    let needs_input = matches!(options.command, Command::Plan | Command::Apply);
+    if needs_input && options.inputs.is_empty() {
+        return Err("Missing required option --input.".to_string());
+    }
+
+    Ok(options)
+}
+
+impl Options {
+    fn output_or_default(&self) -> PathBuf {
+        self.output.clone().unwrap_or_else(|| self.inputs[0].join("output"))
+    }
+
+    fn journal_dir(&self) -> Option<PathBuf> {
+        self.journal_dir.clone().or_else(default_journal_dir)
+    }
+
+    fn devices_file(&self) -> Result<PathBuf, String> {
+        self.devices_file.clone()
+            .or_else(default_devices_file)
+            .ok_or_else(|| "No folder for device profiles is available; use --devices-file.".to_string())
+    }
+
+    fn job(&self) -> Result<RenameJob, String> {
+        let mut job = RenameJob::new(&self.inputs[0], self.output_or_default())
+            .input_merge(self.input_merge)
+            .extension(&self.extension)
+            .no_extension(self.no_extension)
+            .include_original_name(self.include_original_name)
+            .in_place(self.in_place)
+            .transfer_mode(self.transfer_mode)
+            .counter(self.counter)
+            .sort(self.sort)
+            .clock_offsets(ClockOffsets::new(self.clock_offsets.clone()))
+            .normalization(self.normalization)
+            .preserve_tree(self.preserve_tree);
+        for input in &self.inputs[1..] {
+            job = job.add_input(input);
+        }
+        for pattern in &self.include {
+            job = job.include_pattern(pattern);
+        }
+        for pattern in &self.exclude {
+            job = job.exclude_pattern(pattern);
+        }
+        if let Some(template) = &self.template {
+            job = job.template(template);
+        }
+        if let Some(dir) = self.journal_dir() {
+            job = job.journal_dir(dir);
+        }
+        if let Some(writing) = self.write_tags {
+            job = job.write_tags(writing);
+        }
+        if let Some(name) = &self.device {
+            let profiles = DeviceProfile::load(&self.devices_file()?)?;
+            let device = profiles.into_iter()
+                .find(|profile| profile.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("Unknown device profile: {} (run `renamer devices` to list them)", name))?;
+            job = job.device(device);
+        }
+        if let Some(scheme) = self.transliteration {
+            job = job.transliterate(scheme);
+        }
+        if let Some(profile) = self.fs_profile {
+            job = job.fs_profile(profile);
+        }
+        Ok(match self.padding_zeros {
+            Some(padding) => job.padding_zeros(padding),
+            None => job,
+        })
+    }
+}
+
+fn print_report(report: &PlanReport) {
+    if report.is_clean() {
+        println!("No conflicts found.");
+    } else {
+        println!("{} conflicts found:", report.conflicts.len());
+        for conflict in &report.conflicts {
+            println!("  {}", conflict);
+        }
+    }
+}
+
+fn plan(options: &Options) -> Result<(), String> {
+    let plan = options.job()?.plan()?;
+    if plan.is_empty() {
+        return Err("No files found to rename.".to_string());
+    }
+
+    for entry in &plan.entries {
+        println!("{} -> {}", display_path(&entry.source), display_path(&entry.destination));
+        for note in &entry.notes {
+            println!("  warning: {}", note);
+        }
+    }
+    for warning in &plan.warnings {
+        println!("warning: {}", warning);
+    }
+    println!("{} files would be renamed.", plan.len());
+    print_report(&plan.check());
+    Ok(())
+}
+
+fn apply(options: &Options) -> Result<(), String> {
+    let plan = options.job()?.plan()?;
+    let report = plan.check();
+    if !report.is_clean() && !options.force {
+        print_report(&report);
+        return Err("Plan has conflicts; nothing was written. Use --force to apply anyway.".to_string());
+    }
+
+    let mut stream = Box::pin(plan.execute());
+
+    futures::executor::block_on(async {
+        while let Some(event) = stream.next().await {
+            match event {
+                Progress::Renamed { done, total, .. } => {
+                    eprintln!("Renaming... {}/{}", done, total);
+                }
+                Progress::Finished(Ok(files)) => {
+                    for file in &files {
+                        println!("{}", display_path(file));
+                    }
+                    println!("Renaming complete! {} files renamed.", files.len());
+                    return Ok(());
+                }
+                Progress::Finished(Err(e)) => return Err(e),
+                Progress::Cancelled { completed } => {
+                    return Err(format!("Cancelled after {} files; run `renamer undo` to roll them back.", completed.len()));
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+fn devices(options: &Options) -> Result<(), String> {
+    let path = options.devices_file()?;
+    let limit = |limit: Option<usize>| limit.map_or("any".to_string(), |n| n.to_string());
+    for profile in DeviceProfile::load(&path)? {
+        println!(
+            "{}: {}, {} files per folder, folder depth {}{}{}",
+            profile.name,
+            profile.fs,
+            limit(profile.max_files_per_folder),
+            limit(profile.max_depth),
+            if profile.ascii_only { ", ASCII names" } else { "" },
+            if profile.short_names { ", 8.3 names" } else { "" },
+        );
+    }
+    println!("Profiles are kept in {}; edit it to add or change them.", display_path(&path));
+    Ok(())
+}
+
+fn undo(options: &Options) -> Result<(), String> {
+    let dir = options.journal_dir().ok_or("No folder for undo journals is available.")?;
+    let Some(journal) = Journal::latest(&dir)? else {
+        println!("No run to undo.");
+        return Ok(());
+    };
+
+    println!("Undoing {} run of {} files.", journal.mode, journal.entries.len());
+    let report = journal.undo()?;
+    for reason in &report.skipped {
+        println!("  skipped: {}", reason);
+    }
+    println!("{} files reverted, {} skipped.", report.reverted, report.skipped.len());
+    Ok(())
+}
+
+/// Runs the headless interface and returns the process exit code.
+pub fn run(args: &[OsString]) -> i32 {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    let result = match options.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::Plan => plan(&options),
+        Command::Apply => apply(&options),
+        Command::Undo => undo(&options),
+        Command::Devices => devices(&options),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<OsString> {
+        list.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args_apply_with_options() {
+        let options = parse_args(&args(&["apply", "-i", "/music", "--ext", ".flac", "-p", "4", "-n", "--mode", "hardlink"])).unwrap();
+        assert_eq!(options.command, Command::Apply);
+        assert_eq!(options.inputs, vec![PathBuf::from("/music")]);
+        assert_eq!(options.extension, "flac");
+        assert_eq!(options.padding_zeros, Some(4));
+        assert!(options.include_original_name);
+        assert_eq!(options.transfer_mode, TransferMode::HardLink);
+        assert_eq!(options.output_or_default(), PathBuf::from("/music").join("output"));
+    }
+
+    #[test]
+    fn test_parse_args_requires_input() {
+        let err = parse_args(&args(&["plan"])).unwrap_err();
+        assert_eq!(err, "Missing required option --input.");
+    }
+}
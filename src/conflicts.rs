@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::job::RenamePlan;
+use crate::sanitize::collision_key;
+
+/// A problem found in a plan before any file is written.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// The destination already exists and would be overwritten.
+    ExistingTarget(PathBuf),
+    /// Several sources would be written to the same destination.
+    DuplicateTarget { destination: PathBuf, sources: Vec<PathBuf> },
+    /// The destination cannot be created or written to.
+    Unwritable { destination: PathBuf, reason: String },
+    /// Destinations that differ only in case or Unicode form, and so name
+    /// the same file on FAT, NTFS and macOS file systems.
+    Lookalike(Vec<PathBuf>),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::ExistingTarget(destination) => {
+                write!(f, "{} already exists and would be overwritten", destination.to_string_lossy())
+            }
+            Conflict::DuplicateTarget { destination, sources } => {
+                write!(f, "{} files would be written to {}", sources.len(), destination.to_string_lossy())
+            }
+            Conflict::Unwritable { destination, reason } => {
+                write!(f, "{} cannot be written: {}", destination.to_string_lossy(), reason)
+            }
+            Conflict::Lookalike(destinations) => {
+                let names: Vec<_> = destinations.iter().map(|d| d.to_string_lossy()).collect();
+                write!(f, "{} differ only in case or Unicode form and would overwrite each other", names.join(", "))
+            }
+        }
+    }
+}
+
+/// Result of checking a plan against the file system.
+#[derive(Debug, Clone, Default)]
+pub struct PlanReport {
+    pub conflicts: Vec<Conflict>,
+}
+
+impl PlanReport {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+// Walks up to the closest existing ancestor, which is where create_dir_all would start writing.
+fn writability_problem(destination: &Path) -> Option<String> {
+    let mut dir = destination.parent();
+    while let Some(candidate) = dir {
+        match fs::metadata(candidate) {
+            Ok(meta) if !meta.is_dir() => {
+                return Some(format!("{} is not a folder", candidate.to_string_lossy()));
+            }
+            Ok(meta) if meta.permissions().readonly() => {
+                return Some(format!("{} is read-only", candidate.to_string_lossy()));
+            }
+            Ok(_) => return None,
+            Err(_) => dir = candidate.parent(),
+        }
+    }
+    Some("no existing parent folder".to_string())
+}
+
+/// Looks for collisions and unwritable targets. Only reads metadata, never writes.
+pub fn check_plan(plan: &RenamePlan) -> PlanReport {
+    let mut conflicts = Vec::new();
+
+    let mut by_destination: HashMap<&Path, Vec<PathBuf>> = HashMap::new();
+    for entry in &plan.entries {
+        by_destination.entry(&entry.destination).or_default().push(entry.source.clone());
+    }
+
+    // In-place renames may take a name another file of the plan is giving up.
+    let released: HashSet<&Path> = if plan.in_place {
+        plan.entries.iter().map(|e| e.source.as_path()).collect()
+    } else {
+        HashSet::new()
+    };
+
+    let mut reported = HashSet::new();
+    for entry in &plan.entries {
+        let destination = entry.destination.as_path();
+        if !reported.insert(destination) {
+            continue;
+        }
+
+        let sources = &by_destination[destination];
+        if sources.len() > 1 {
+            conflicts.push(Conflict::DuplicateTarget {
+                destination: destination.to_path_buf(),
+                sources: sources.clone(),
+            });
+        }
+
+        match fs::symlink_metadata(destination) {
+            Ok(meta) if meta.is_dir() => conflicts.push(Conflict::Unwritable {
+                destination: destination.to_path_buf(),
+                reason: "a folder with this name exists".to_string(),
+            }),
+            Ok(_) if released.contains(destination) => {}
+            Ok(_) => conflicts.push(Conflict::ExistingTarget(destination.to_path_buf())),
+            Err(_) => {
+                if let Some(reason) = writability_problem(destination) {
+                    conflicts.push(Conflict::Unwritable { destination: destination.to_path_buf(), reason });
+                }
+            }
+        }
+    }
+
+    if !plan.case_sensitive {
+        let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+        let mut group_of_key: HashMap<String, usize> = HashMap::new();
+        let mut seen = HashSet::new();
+        for entry in &plan.entries {
+            let destination = entry.destination.as_path();
+            let Some(text) = destination.to_str() else { continue };
+            if !seen.insert(destination) {
+                continue;
+            }
+            let group = *group_of_key.entry(collision_key(text)).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(destination.to_path_buf());
+        }
+        conflicts.extend(groups.into_iter().filter(|group| group.len() > 1).map(Conflict::Lookalike));
+    }
+
+    PlanReport { conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::PlannedRename;
+    use crate::transfer::TransferMode;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_plan_reports_existing_and_duplicate_targets() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("001.mp3")).unwrap();
+
+        let plan = RenamePlan {
+            output_dir: dir.path().to_path_buf(),
+            padding_zeros: 3,
+            in_place: false,
+            transfer_mode: TransferMode::Copy,
+            journal_dir: None,
+            entries: vec![
+                PlannedRename { source: "a.mp3".into(), destination: dir.path().join("001.mp3"), notes: Vec::new(), tag_update: None },
+                PlannedRename { source: "b.mp3".into(), destination: dir.path().join("002.mp3"), notes: Vec::new(), tag_update: None },
+                PlannedRename { source: "c.mp3".into(), destination: dir.path().join("002.mp3"), notes: Vec::new(), tag_update: None },
+            ],
+            warnings: Vec::new(),
+            case_sensitive: false,
+        };
+
+        let report = check_plan(&plan);
+        assert_eq!(report.conflicts, vec![
+            Conflict::ExistingTarget(dir.path().join("001.mp3")),
+            Conflict::DuplicateTarget {
+                destination: dir.path().join("002.mp3"),
+                sources: vec!["b.mp3".into(), "c.mp3".into()],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_check_plan_reports_file_in_place_of_folder() {
+        let dir = tempdir().unwrap();
+        File::create(dir.path().join("output")).unwrap();
+
+        let plan = RenamePlan {
+            output_dir: dir.path().join("output"),
+            padding_zeros: 3,
+            in_place: false,
+            transfer_mode: TransferMode::Copy,
+            journal_dir: None,
+            entries: vec![
+                PlannedRename { source: "a.mp3".into(), destination: dir.path().join("output").join("001.mp3"), notes: Vec::new(), tag_update: None },
+            ],
+            warnings: Vec::new(),
+            case_sensitive: false,
+        };
+
+        let report = check_plan(&plan);
+        assert!(matches!(report.conflicts.as_slice(), [Conflict::Unwritable { .. }]));
+    }
+
+    #[test]
+    fn test_check_plan_reports_names_differing_in_case_or_form() {
+        let dir = tempdir().unwrap();
+        let entry = |source: &str, name: &str| PlannedRename {
+            source: source.into(),
+            destination: dir.path().join(name),
+            notes: Vec::new(),
+            tag_update: None,
+        };
+        let mut plan = RenamePlan {
+            output_dir: dir.path().to_path_buf(),
+            padding_zeros: 1,
+            in_place: false,
+            transfer_mode: TransferMode::Copy,
+            journal_dir: None,
+            entries: vec![
+                entry("a.mp3", "1_Caf\u{e9}.mp3"),
+                entry("b.mp3", "1_CAFE\u{301}.mp3"),
+                entry("c.mp3", "2_other.mp3"),
+            ],
+            warnings: Vec::new(),
+            case_sensitive: false,
+        };
+
+        assert_eq!(check_plan(&plan).conflicts, vec![Conflict::Lookalike(vec![
+            dir.path().join("1_Caf\u{e9}.mp3"),
+            dir.path().join("1_CAFE\u{301}.mp3"),
+        ])]);
+        plan.case_sensitive = true;
+        assert!(check_plan(&plan).is_clean());
+    }
+}
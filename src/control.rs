@@ -0,0 +1,123 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+const COPY_CHUNK: usize = 1024 * 1024;
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
+/// Shared switch to pause, resume or cancel a running plan from another thread.
+///
+/// Clones control the same run. The plan looks at it between files and, for
+/// copies, between chunks of a file.
+#[derive(Debug, Clone, Default)]
+pub struct RunControl {
+    cancelled: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+}
+
+impl RunControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Blocks while paused. Returns `false` if the run was cancelled meanwhile.
+    pub fn proceed(&self) -> bool {
+        while self.is_paused() && !self.is_cancelled() {
+            thread::sleep(PAUSE_POLL);
+        }
+        !self.is_cancelled()
+    }
+}
+
+/// Copies in chunks so that a cancel takes effect in the middle of a large
+/// file. A cancelled copy fails with [`io::ErrorKind::Interrupted`]; a copy
+/// that does not finish, for whatever reason, removes the partial destination.
+pub fn copy_with_control(source: &Path, destination: &Path, control: &RunControl) -> io::Result<()> {
+    let reader = File::open(source)?;
+    let writer = File::create(destination)?;
+    let copied = copy_chunks(reader, writer, control);
+    if copied.is_err() {
+        let _ = fs::remove_file(destination);
+    }
+    copied
+}
+
+fn copy_chunks(mut reader: File, mut writer: File, control: &RunControl) -> io::Result<()> {
+    let mut buffer = vec![0; COPY_CHUNK];
+    loop {
+        if !control.proceed() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+    }
+    writer.set_permissions(reader.metadata()?.permissions())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_with_control_copies_contents() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("a.mp3");
+        fs::write(&source, vec![7u8; COPY_CHUNK + 10]).unwrap();
+
+        copy_with_control(&source, &dir.path().join("b.mp3"), &RunControl::default()).unwrap();
+
+        assert_eq!(fs::read(dir.path().join("b.mp3")).unwrap().len(), COPY_CHUNK + 10);
+    }
+
+    #[test]
+    fn test_copy_with_control_cancelled_leaves_no_partial_file() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("a.mp3");
+        fs::write(&source, "data").unwrap();
+        let control = RunControl::default();
+        control.pause();
+        control.cancel();
+
+        let err = copy_with_control(&source, &dir.path().join("b.mp3"), &control).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert!(!dir.path().join("b.mp3").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_with_control_failed_read_leaves_no_partial_file() {
+        let dir = tempdir().unwrap();
+        // A folder opens like a file on Unix, but reading it fails.
+        let source = dir.path().join("album");
+        fs::create_dir(&source).unwrap();
+
+        assert!(copy_with_control(&source, &dir.path().join("b.mp3"), &RunControl::default()).is_err());
+        assert!(!dir.path().join("b.mp3").exists());
+    }
+}
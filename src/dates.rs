@@ -0,0 +1,445 @@
+//! Capture dates of photos and videos, used for ordering and the `{date}` token.
+//!
+//! Each file's date comes from the first of these that it has:
+//!
+//! 1. EXIF `DateTimeOriginal` (JPEG, HEIC, TIFF-based RAW such as CR2, NEF, ARW, DNG)
+//! 2. EXIF `DateTimeDigitized`
+//! 3. EXIF `DateTime`
+//! 4. The creation time in the `mvhd` atom of an MP4 or MOV file
+//! 5. The file's modification time
+//!
+//! EXIF dates carry no time zone unless an `OffsetTime*` field is present, so
+//! they are taken as local time. Video creation times are UTC.
+//!
+//! Cameras with a wrong clock are corrected with [`ClockOffsets`], matched by
+//! folder or by the camera's EXIF make and model.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeDelta, TimeZone, Utc};
+use exif::{In, Tag};
+
+/// Where a capture date was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateSource {
+    ExifOriginal,
+    ExifDigitized,
+    ExifModified,
+    VideoCreated,
+    /// The file carried no date of its own.
+    FileModified,
+}
+
+impl fmt::Display for DateSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DateSource::ExifOriginal => "EXIF DateTimeOriginal",
+            DateSource::ExifDigitized => "EXIF DateTimeDigitized",
+            DateSource::ExifModified => "EXIF DateTime",
+            DateSource::VideoCreated => "video creation time",
+            DateSource::FileModified => "file modification time",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDate {
+    pub time: DateTime<Local>,
+    pub source: DateSource,
+    /// EXIF make and model, e.g. `Canon EOS 80D`.
+    pub camera: Option<String>,
+}
+
+impl CaptureDate {
+    /// Reads the date of `path` through the fallback chain described in the module docs.
+    pub fn read(path: &Path) -> Option<Self> {
+        let (exif_date, camera) = read_exif(path).unwrap_or_default();
+        exif_date
+            .or_else(|| video_created(path).map(|time| (time, DateSource::VideoCreated)))
+            .or_else(|| {
+                let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+                Some((DateTime::<Local>::from(modified), DateSource::FileModified))
+            })
+            .map(|(time, source)| Self { time, source, camera })
+    }
+
+    /// Like [`CaptureDate::read`], with the first matching offset of `offsets` applied.
+    pub fn read_adjusted(path: &Path, offsets: &ClockOffsets) -> Option<Self> {
+        let mut date = Self::read(path)?;
+        if let Some(offset) = offsets.find(path, date.camera.as_deref()) {
+            date.time = date.time.checked_add_signed(offset)?;
+        }
+        Some(date)
+    }
+
+    /// Preview warning for dates that did not come from the file's own metadata.
+    pub fn note(date: Option<&Self>) -> Option<String> {
+        match date {
+            None => Some("no capture date".to_string()),
+            Some(date) if date.source == DateSource::FileModified => {
+                Some("no capture date in the file; used its modification time".to_string())
+            }
+            Some(_) => None,
+        }
+    }
+}
+
+const EXIF_DATES: [(Tag, Tag, DateSource); 3] = [
+    (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal, DateSource::ExifOriginal),
+    (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized, DateSource::ExifDigitized),
+    (Tag::DateTime, Tag::OffsetTime, DateSource::ExifModified),
+];
+
+type ExifDate = (DateTime<Local>, DateSource);
+
+// The first EXIF date of the fallback chain, and the camera make and model.
+fn read_exif(path: &Path) -> Option<(Option<ExifDate>, Option<String>)> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let ascii = |tag: Tag| match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(exif::Value::Ascii(values)) => values.first().cloned(),
+        _ => None,
+    };
+    let text = |tag: Tag| {
+        ascii(tag)
+            .map(|bytes| String::from_utf8_lossy(&bytes).trim_end_matches('\0').trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    // Models often repeat the make ("Canon" / "Canon EOS 80D").
+    let camera = match (text(Tag::Make), text(Tag::Model)) {
+        (Some(make), Some(model)) if model.to_lowercase().starts_with(&make.to_lowercase()) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+
+    let date = EXIF_DATES.iter().find_map(|&(date_tag, offset_tag, source)| {
+        let mut date = exif::DateTime::from_ascii(&ascii(date_tag)?).ok()?;
+        if let Some(offset) = ascii(offset_tag) {
+            let _ = date.parse_offset(&offset);
+        }
+        let naive = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())?
+            .and_hms_opt(date.hour.into(), date.minute.into(), date.second.into())?;
+        let time = match date.offset {
+            Some(minutes) => FixedOffset::east_opt(i32::from(minutes) * 60)?
+                .from_local_datetime(&naive)
+                .single()?
+                .with_timezone(&Local),
+            None => Local.from_local_datetime(&naive).earliest()?,
+        };
+        Some((time, source))
+    });
+    Some((date, camera))
+}
+
+/// Files a clock offset applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OffsetTarget {
+    /// Every file inside this folder. Relative folders are looked up in the input folders.
+    Folder(PathBuf),
+    /// Files whose EXIF make and model match, ignoring case.
+    Camera(String),
+}
+
+/// Time added to the capture dates of some files before they are sorted or named.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockOffset {
+    pub target: OffsetTarget,
+    pub offset: TimeDelta,
+}
+
+/// Parses an offset such as `+5m`, `-1h30m`, `90s`, `+2d` or `-02:00`. Only the
+/// offset as a whole takes a sign, never its parts.
+pub fn parse_offset(raw: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("Invalid clock offset '{}'; use e.g. +5m, -1h30m or +02:00", raw);
+    let trimmed = raw.trim();
+    let (negative, body) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    if body.is_empty() {
+        return Err(invalid());
+    }
+
+    let seconds = if body.contains(':') {
+        let parts = body.split(':')
+            .map(|part| match !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) {
+                true => part.parse::<i64>().map_err(|_| invalid()),
+                false => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (hours, minutes, seconds) = match parts[..] {
+            [hours, minutes] => (hours, minutes, 0),
+            [hours, minutes, seconds] => (hours, minutes, seconds),
+            _ => return Err(invalid()),
+        };
+        hours.checked_mul(3600)
+            .and_then(|total| total.checked_add(minutes.checked_mul(60)?))
+            .and_then(|total| total.checked_add(seconds))
+            .ok_or_else(invalid)?
+    } else {
+        let mut total = 0i64;
+        let mut number = String::new();
+        for c in body.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let unit = match c {
+                'd' => 86_400,
+                'h' => 3_600,
+                'm' => 60,
+                's' => 1,
+                _ => return Err(invalid()),
+            };
+            total = number.parse::<i64>().ok()
+                .and_then(|n| n.checked_mul(unit))
+                .and_then(|seconds| total.checked_add(seconds))
+                .ok_or_else(invalid)?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err(invalid());
+        }
+        total
+    };
+    let seconds = if negative { seconds.checked_neg() } else { Some(seconds) };
+    seconds.and_then(TimeDelta::try_seconds).ok_or_else(invalid)
+}
+
+impl FromStr for ClockOffset {
+    type Err = String;
+
+    /// Parses `FOLDER=OFFSET` or `camera:MAKE MODEL=OFFSET`, e.g. `camera:Canon EOS 80D=-3m`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, offset) = s.rsplit_once('=')
+            .ok_or_else(|| format!("Clock offset '{}' needs the form FOLDER=OFFSET or camera:MODEL=OFFSET", s))?;
+        let target = target.trim();
+        let target = match target.strip_prefix("camera:") {
+            Some(camera) if !camera.trim().is_empty() => OffsetTarget::Camera(camera.trim().to_string()),
+            None if !target.is_empty() => OffsetTarget::Folder(PathBuf::from(target)),
+            _ => return Err(format!("Clock offset '{}' does not name a folder or camera", s)),
+        };
+        Ok(Self { target, offset: parse_offset(offset)? })
+    }
+}
+
+/// Clock offsets of a job. The first one matching a file is applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClockOffsets {
+    offsets: Vec<ClockOffset>,
+}
+
+impl ClockOffsets {
+    pub fn new(offsets: Vec<ClockOffset>) -> Self {
+        Self { offsets }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Turns each relative folder into that folder inside every input folder,
+    /// and into any input folder that ends with it, e.g. `Phone` matches the
+    /// input `/photos/Phone`.
+    pub fn resolved(&self, inputs: &[PathBuf]) -> Self {
+        let offsets = self.offsets.iter()
+            .flat_map(|offset| {
+                let folders = match &offset.target {
+                    OffsetTarget::Folder(folder) if folder.is_relative() => inputs.iter()
+                        .flat_map(|input| {
+                            let own = input.ends_with(folder).then(|| input.clone());
+                            own.into_iter().chain([input.join(folder)])
+                        })
+                        .collect(),
+                    OffsetTarget::Folder(folder) => vec![folder.clone()],
+                    OffsetTarget::Camera(_) => return vec![offset.clone()],
+                };
+                folders.into_iter()
+                    .map(|folder| ClockOffset { target: OffsetTarget::Folder(folder), offset: offset.offset })
+                    .collect()
+            })
+            .collect();
+        Self { offsets }
+    }
+
+    fn find(&self, path: &Path, camera: Option<&str>) -> Option<TimeDelta> {
+        self.offsets.iter()
+            .find(|offset| match &offset.target {
+                OffsetTarget::Folder(folder) => path.starts_with(folder),
+                OffsetTarget::Camera(model) => camera.is_some_and(|camera| camera.eq_ignore_ascii_case(model)),
+            })
+            .map(|offset| offset.offset)
+    }
+}
+
+impl FromStr for ClockOffsets {
+    type Err = String;
+
+    /// Parses offsets separated by `;`, as typed into the graphical interface.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(';')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::new)
+    }
+}
+
+// Seconds between 1904-01-01, the QuickTime epoch, and 1970-01-01.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+// Reads the header of the next box: its type and the size of its body.
+fn read_box_header(reader: &mut impl Read, remaining: u64) -> io::Result<Option<([u8; 4], u64)>> {
+    if remaining < 8 {
+        return Ok(None);
+    }
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let size = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+    let kind = [header[4], header[5], header[6], header[7]];
+    if !kind.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return Ok(None);
+    }
+    let body = match size {
+        0 => remaining - 8,
+        1 => {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            u64::from_be_bytes(large).checked_sub(16).ok_or(io::ErrorKind::InvalidData)?
+        }
+        size => size.checked_sub(8).ok_or(io::ErrorKind::InvalidData)?,
+    };
+    Ok(Some((kind, body)))
+}
+
+// Finds the box of type `kind` among the next `remaining` bytes and returns its body size.
+fn find_box(reader: &mut (impl Read + Seek), kind: &[u8; 4], mut remaining: u64) -> io::Result<Option<u64>> {
+    while let Some((found, body)) = read_box_header(reader, remaining)? {
+        if &found == kind {
+            return Ok(Some(body));
+        }
+        reader.seek(SeekFrom::Current(body as i64))?;
+        remaining = remaining.saturating_sub(body + 8);
+    }
+    Ok(None)
+}
+
+/// Creation time from the `moov/mvhd` atom of an MP4 or MOV file.
+fn video_created(path: &Path) -> Option<DateTime<Local>> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let len = reader.get_ref().metadata().ok()?.len();
+    let moov = find_box(&mut reader, b"moov", len).ok()??;
+    find_box(&mut reader, b"mvhd", moov).ok()??;
+
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version).ok()?;
+    let seconds = if version[0] == 1 {
+        let mut created = [0u8; 8];
+        reader.read_exact(&mut created).ok()?;
+        u64::from_be_bytes(created)
+    } else {
+        let mut created = [0u8; 4];
+        reader.read_exact(&mut created).ok()?;
+        u64::from(u32::from_be_bytes(created))
+    };
+    // Zero means the recorder did not set the field.
+    if seconds == 0 {
+        return None;
+    }
+    let unix = i64::try_from(seconds).ok()? - QUICKTIME_EPOCH_OFFSET;
+    Utc.timestamp_opt(unix, 0).single().map(|time| time.with_timezone(&Local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::{Field, Value};
+    use tempfile::tempdir;
+
+    fn write_tiff(path: &Path, fields: &[(Tag, &str)]) {
+        let fields: Vec<Field> = fields.iter()
+            .map(|(tag, value)| Field { tag: *tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![value.as_bytes().to_vec()]) })
+            .collect();
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut bytes = io::Cursor::new(Vec::new());
+        writer.write(&mut bytes, false).unwrap();
+        fs::write(path, bytes.into_inner()).unwrap();
+    }
+
+    #[test]
+    fn test_exif_fallback_chain() {
+        let dir = tempdir().unwrap();
+        let original = dir.path().join("a.tif");
+        write_tiff(&original, &[(Tag::DateTime, "2024:01:02 03:04:05"), (Tag::DateTimeOriginal, "2023:07:14 18:30:00")]);
+        let date = CaptureDate::read(&original).unwrap();
+        assert_eq!(date.source, DateSource::ExifOriginal);
+        assert_eq!(date.time.format("%Y%m%d-%H%M%S").to_string(), "20230714-183000");
+
+        let modified = dir.path().join("b.tif");
+        write_tiff(&modified, &[(Tag::DateTime, "2024:01:02 03:04:05")]);
+        assert_eq!(CaptureDate::read(&modified).unwrap().source, DateSource::ExifModified);
+
+        let plain = dir.path().join("c.jpg");
+        fs::write(&plain, "not an image").unwrap();
+        let date = CaptureDate::read(&plain);
+        assert_eq!(date.as_ref().unwrap().source, DateSource::FileModified);
+        assert!(CaptureDate::note(date.as_ref()).is_some());
+    }
+
+    #[test]
+    fn test_clock_offsets_by_folder_and_camera() {
+        assert_eq!(parse_offset("-1h30m").unwrap(), TimeDelta::minutes(-90));
+        assert_eq!(parse_offset("+02:00").unwrap(), TimeDelta::hours(2));
+        assert!(parse_offset("5 minutes").is_err());
+        assert!(parse_offset("999999999999999d").is_err());
+        assert!(parse_offset("9223372036854775807s").is_err());
+        assert!(parse_offset("-9223372036854775807:00").is_err());
+        assert!(parse_offset("-0:0:-9223372036854775808").is_err());
+        assert!(parse_offset("+1:-30").is_err());
+        assert!(parse_offset("1:+30").is_err());
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("phone")).unwrap();
+        let phone = dir.path().join("phone").join("a.tif");
+        write_tiff(&phone, &[(Tag::Make, "Google"), (Tag::Model, "Pixel 7"), (Tag::DateTimeOriginal, "2023:07:14 18:30:00")]);
+        let camera = dir.path().join("b.tif");
+        write_tiff(&camera, &[(Tag::Make, "Canon"), (Tag::Model, "Canon EOS 80D"), (Tag::DateTimeOriginal, "2023:07:14 18:30:00")]);
+
+        let offsets: ClockOffsets = "camera:canon eos 80d=-3m; phone=+1h".parse().unwrap();
+        let offsets = offsets.resolved(&[dir.path().to_path_buf()]);
+        let adjusted = |path: &Path| CaptureDate::read_adjusted(path, &offsets).unwrap().time.format("%H:%M").to_string();
+        assert_eq!(adjusted(&camera), "18:27");
+        assert_eq!(adjusted(&phone), "19:30");
+        assert_eq!(CaptureDate::read(&camera).unwrap().camera.as_deref(), Some("Canon EOS 80D"));
+    }
+
+    #[test]
+    fn test_video_creation_time() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        let mut bytes = Vec::new();
+        bytes.extend(16u32.to_be_bytes());
+        bytes.extend(b"ftypisom\0\0\0\0");
+        // moov containing a version 0 mvhd; 2020-01-01T00:00:00Z is 3_660_681_600 s after 1904.
+        bytes.extend(28u32.to_be_bytes());
+        bytes.extend(b"moov");
+        bytes.extend(20u32.to_be_bytes());
+        bytes.extend(b"mvhd");
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(3_660_681_600u32.to_be_bytes());
+        bytes.extend([0; 4]);
+        fs::write(&path, bytes).unwrap();
+
+        let date = CaptureDate::read(&path).unwrap();
+        assert_eq!(date.source, DateSource::VideoCreated);
+        assert_eq!(date.time.with_timezone(&Utc).to_rfc3339(), "2020-01-01T00:00:00+00:00");
+    }
+}
@@ -0,0 +1,471 @@
+//! Device profiles: the limits of a car stereo or a simple player, bundled
+//! under a name.
+//!
+//! Applying a profile fits the output to the device: folders deeper than it
+//! reads are merged into their ancestor at the deepest level it does read,
+//! folders holding more files than it shows are split into numbered parts,
+//! and names are spelled in ASCII or cut to 8.3 form when it needs that.
+//!
+//! Profiles are kept in a plain text file that users may edit:
+//!
+//! ```text
+//! [Car stereo]
+//! file system = fat32
+//! files per folder = 255
+//! folder depth = 1
+//! ascii only = yes
+//! short names = no
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::job::PlannedRename;
+use crate::sanitize::FsProfile;
+use crate::translit::Transliteration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub name: String,
+    pub fs: FsProfile,
+    /// Most files the device shows in one folder.
+    pub max_files_per_folder: Option<usize>,
+    /// Most folder levels below the output folder the device reads.
+    pub max_depth: Option<usize>,
+    /// Names are spelled in ASCII.
+    pub ascii_only: bool,
+    /// Names are cut to 8.3 form, e.g. `001-SONG.MP3`.
+    pub short_names: bool,
+}
+
+/// Where the front ends keep the device profiles.
+pub fn default_devices_file() -> Option<PathBuf> {
+    dirs_next::config_dir().map(|dir| dir.join("renamer").join("devices.txt"))
+}
+
+const HEADER: &str = "\
+# Renamer device profiles. Each profile starts with its name in brackets.
+# Settings: file system (fat32, exfat, ntfs, ext4), files per folder and
+# folder depth (a number or \"any\"), ascii only and short names (yes or no).
+";
+
+fn parse_limit(value: &str) -> Result<Option<usize>, String> {
+    match value {
+        "any" | "" => Ok(None),
+        _ => value.parse().map(Some).map_err(|_| format!("expected a number or \"any\", found '{}'", value)),
+    }
+}
+
+fn parse_flag(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "true" => Ok(true),
+        "no" | "false" => Ok(false),
+        _ => Err(format!("expected yes or no, found '{}'", value)),
+    }
+}
+
+fn format_limit(limit: Option<usize>) -> String {
+    limit.map_or("any".to_string(), |n| n.to_string())
+}
+
+impl DeviceProfile {
+    /// A profile without limits, writing to `fs`.
+    pub fn new(name: impl Into<String>, fs: FsProfile) -> Self {
+        Self {
+            name: name.into(),
+            fs,
+            max_files_per_folder: None,
+            max_depth: None,
+            ascii_only: false,
+            short_names: false,
+        }
+    }
+
+    /// Profiles offered before the user has written any.
+    pub fn built_in() -> Vec<Self> {
+        vec![
+            Self {
+                max_files_per_folder: Some(255),
+                max_depth: Some(1),
+                ascii_only: true,
+                ..Self::new("Car stereo", FsProfile::Fat32)
+            },
+            Self {
+                max_files_per_folder: Some(999),
+                max_depth: Some(2),
+                ascii_only: true,
+                short_names: true,
+                ..Self::new("Simple MP3 player", FsProfile::Fat32)
+            },
+            Self::new("USB stick", FsProfile::ExFat),
+        ]
+    }
+
+    /// Reads the profiles in `path`. A missing file is created with the built-in profiles.
+    pub fn load(path: &Path) -> Result<Vec<Self>, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.to_string_lossy(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let profiles = Self::built_in();
+                // The file is only there to be edited; not being able to write it is no reason to fail.
+                let _ = Self::save(&profiles, path);
+                Ok(profiles)
+            }
+            Err(e) => Err(format!("{}: {}", path.to_string_lossy(), e)),
+        }
+    }
+
+    pub fn save(profiles: &[Self], path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(path, Self::format(profiles)).map_err(|e| e.to_string())
+    }
+
+    pub fn parse(text: &str) -> Result<Vec<Self>, String> {
+        let mut profiles: Vec<Self> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let fail = |message: String| format!("line {}: {}", number + 1, message);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                profiles.push(Self::new(name.trim(), FsProfile::Fat32));
+                continue;
+            }
+            let profile = profiles.last_mut().ok_or_else(|| fail("setting before the first [profile name]".to_string()))?;
+            let (key, value) = line.split_once('=').ok_or_else(|| fail(format!("expected 'setting = value', found '{}'", line)))?;
+            let value = value.trim();
+            match key.trim().to_lowercase().as_str() {
+                "file system" => profile.fs = value.parse().map_err(fail)?,
+                "files per folder" => profile.max_files_per_folder = parse_limit(value).map_err(fail)?,
+                "folder depth" => profile.max_depth = parse_limit(value).map_err(fail)?,
+                "ascii only" => profile.ascii_only = parse_flag(value).map_err(fail)?,
+                "short names" => profile.short_names = parse_flag(value).map_err(fail)?,
+                other => return Err(fail(format!("unknown setting '{}'", other))),
+            }
+        }
+        Ok(profiles)
+    }
+
+    pub fn format(profiles: &[Self]) -> String {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let mut text = HEADER.to_string();
+        for profile in profiles {
+            text.push_str(&format!(
+                "\n[{}]\nfile system = {}\nfiles per folder = {}\nfolder depth = {}\nascii only = {}\nshort names = {}\n",
+                profile.name,
+                profile.fs,
+                format_limit(profile.max_files_per_folder),
+                format_limit(profile.max_depth),
+                yes_no(profile.ascii_only),
+                yes_no(profile.short_names),
+            ));
+        }
+        text
+    }
+
+    /// Moves and renames the planned files under `output` to fit the device,
+    /// returning what was changed, or what could not be, as plan warnings.
+    pub(crate) fn fit(&self, entries: &mut [PlannedRename], output: &Path, scheme: Transliteration) -> Vec<String> {
+        let mut merged = HashSet::new();
+        let mut folders: Vec<Vec<String>> = Vec::with_capacity(entries.len());
+        for entry in entries.iter() {
+            let relative = entry.destination.strip_prefix(output).unwrap_or(&entry.destination);
+            let mut parts: Vec<String> = relative.parent()
+                .into_iter()
+                .flat_map(Path::components)
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                    _ => None,
+                })
+                .collect();
+            if let Some(depth) = self.max_depth.filter(|depth| parts.len() > *depth) {
+                merged.insert(parts.clone());
+                let deeper = parts.split_off(depth.saturating_sub(1)).join(" - ");
+                if depth > 0 {
+                    parts.push(deeper);
+                }
+            }
+            for part in &mut parts {
+                if self.ascii_only {
+                    *part = scheme.apply(part);
+                }
+                let sanitized = self.fs.sanitize_name(OsStr::new(part.as_str()), self.fs.name_limit());
+                *part = sanitized.name.to_string_lossy().into_owned();
+            }
+            folders.push(parts);
+        }
+
+        let mut short = ShortNames::default();
+        let mut placed: Vec<PathBuf> = folders.iter()
+            .map(|parts| if self.short_names { short.folder(parts) } else { parts.iter().collect() })
+            .collect();
+
+        let mut warnings = Vec::new();
+        if let Some(limit) = self.max_files_per_folder.filter(|limit| *limit > 0) {
+            let mut sizes: HashMap<&PathBuf, usize> = HashMap::new();
+            for folder in &placed {
+                *sizes.entry(folder).or_default() += 1;
+            }
+            let crowded: HashMap<PathBuf, usize> = sizes.into_iter()
+                .filter(|(_, size)| *size > limit)
+                .map(|(folder, size)| (folder.clone(), size))
+                .collect();
+            let room_to_split = self.max_depth.is_none_or(|depth| depth > 0);
+            if !crowded.is_empty() && room_to_split {
+                let mut positions: HashMap<PathBuf, usize> = HashMap::new();
+                let mut parts: HashMap<(PathBuf, usize), PathBuf> = HashMap::new();
+                for folder in &mut placed {
+                    let Some(size) = crowded.get(folder) else { continue };
+                    let position = positions.entry(folder.clone()).or_default();
+                    let part = *position / limit + 1;
+                    *position += 1;
+                    let width = size.div_ceil(limit).to_string().len();
+                    let split = parts.entry((folder.clone(), part)).or_insert_with(|| {
+                        let split = part_folder(folder, part, width, self.short_names);
+                        if !self.short_names {
+                            return split;
+                        }
+                        // Cut to 8.3, parts of two folders starting alike would otherwise share a name.
+                        let parent = split.parent().unwrap_or(Path::new("")).to_path_buf();
+                        let name = short.unique(&parent, split.file_name().unwrap_or_default().to_string_lossy().into_owned());
+                        parent.join(name)
+                    });
+                    *folder = split.clone();
+                }
+                let mut sizes: HashMap<&PathBuf, usize> = HashMap::new();
+                for folder in &placed {
+                    *sizes.entry(folder).or_default() += 1;
+                }
+                let still_crowded = sizes.values().filter(|size| **size > limit).count();
+                if still_crowded == 0 {
+                    warnings.push(format!(
+                        "{}: {} folders split into parts of at most {} files",
+                        self.name, crowded.len(), limit
+                    ));
+                } else {
+                    warnings.push(format!(
+                        "{}: {} folders still hold more than {} files; the device may not show them all",
+                        self.name, still_crowded, limit
+                    ));
+                }
+            } else if !crowded.is_empty() {
+                warnings.push(format!(
+                    "{}: more than {} files end up in one folder; the device may not show them all",
+                    self.name, limit
+                ));
+            }
+        }
+        if !merged.is_empty() {
+            let depth = self.max_depth.unwrap_or_default();
+            warnings.push(format!(
+                "{}: {} folders merged to stay within a folder depth of {}",
+                self.name, merged.len(), depth
+            ));
+        }
+
+        let mut shortened = 0;
+        for (entry, folder) in entries.iter_mut().zip(placed) {
+            let mut name = entry.destination.file_name().unwrap_or_default().to_os_string();
+            if self.short_names {
+                let short_name = short.file(&folder, &name.to_string_lossy());
+                if name != short_name.as_str() {
+                    shortened += 1;
+                }
+                name = short_name.into();
+            }
+            entry.destination = output.join(folder).join(name);
+        }
+        if shortened > 0 {
+            warnings.push(format!("{}: {} names cut to 8.3 form", self.name, shortened));
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for DeviceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+// The folder for part `part` of a split folder: `Album (2)`, or `01`, `02`
+// for the output folder itself.
+fn part_folder(folder: &Path, part: usize, width: usize, short: bool) -> PathBuf {
+    let number = format!("{:0width$}", part, width = width);
+    let Some(name) = folder.file_name() else {
+        return PathBuf::from(number);
+    };
+    let name = name.to_string_lossy();
+    let renamed = if short {
+        let keep = 8usize.saturating_sub(number.len() + 1);
+        format!("{}_{}", name.chars().take(keep).collect::<String>(), number)
+    } else {
+        format!("{} ({})", name, number)
+    };
+    folder.with_file_name(renamed)
+}
+
+/// Hands out 8.3 names, keeping them unique within each folder the way
+/// Windows does, with `~1`, `~2` and so on.
+#[derive(Default)]
+struct ShortNames {
+    /// Short names already given out, per folder.
+    taken: HashMap<PathBuf, HashSet<String>>,
+    /// Short form of every long folder path seen so far.
+    folders: HashMap<Vec<String>, PathBuf>,
+}
+
+impl ShortNames {
+    fn folder(&mut self, parts: &[String]) -> PathBuf {
+        let mut short = PathBuf::new();
+        for depth in 1..=parts.len() {
+            let known = self.folders.get(&parts[..depth]).cloned();
+            short = match known {
+                Some(folder) => folder,
+                None => {
+                    let name = self.unique(&short, short_name(&parts[depth - 1], false));
+                    let folder = short.join(name);
+                    self.folders.insert(parts[..depth].to_vec(), folder.clone());
+                    folder
+                }
+            };
+        }
+        short
+    }
+
+    fn file(&mut self, folder: &Path, name: &str) -> String {
+        self.unique(folder, short_name(name, true))
+    }
+
+    fn unique(&mut self, folder: &Path, name: String) -> String {
+        let taken = self.taken.entry(folder.to_path_buf()).or_default();
+        let (stem, ext) = name.split_once('.').map_or((name.as_str(), ""), |(stem, ext)| (stem, ext));
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while taken.contains(&candidate) {
+            let suffix = format!("~{}", n);
+            let stem: String = stem.chars().take(8 - suffix.len()).collect();
+            candidate = if ext.is_empty() { format!("{}{}", stem, suffix) } else { format!("{}{}.{}", stem, suffix, ext) };
+            n += 1;
+        }
+        taken.insert(candidate.clone());
+        candidate
+    }
+}
+
+/// `name` in 8.3 form: upper case, at most 8 characters before the dot and
+/// 3 after it, spaces and extra dots dropped, other characters FAT does not
+/// allow in short names replaced with `_`.
+fn short_name(name: &str, has_extension: bool) -> String {
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if has_extension && dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    let clean = |text: &str, max: usize| -> String {
+        text.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| {
+                let c = c.to_ascii_uppercase();
+                if c.is_ascii_alphanumeric() || "!#$%&'()-@^_`{}~".contains(c) { c } else { '_' }
+            })
+            .take(max)
+            .collect()
+    };
+    let stem = clean(stem, 8);
+    let stem = if stem.is_empty() { "_".to_string() } else { stem };
+    let ext = clean(ext, 3);
+    if ext.is_empty() { stem } else { format!("{}.{}", stem, ext) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(output: &Path, relative: &str) -> PlannedRename {
+        PlannedRename {
+            source: relative.into(),
+            destination: output.join(relative),
+            notes: Vec::new(),
+            tag_update: None,
+        }
+    }
+
+    #[test]
+    fn test_profiles_round_trip_through_text() {
+        let profiles = DeviceProfile::built_in();
+        assert_eq!(DeviceProfile::parse(&DeviceProfile::format(&profiles)), Ok(profiles));
+        assert_eq!(
+            DeviceProfile::parse("[Old player]\nfolder depth = two"),
+            Err("line 2: expected a number or \"any\", found 'two'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_fit_merges_deep_folders_and_splits_crowded_ones() {
+        let output = Path::new("/out");
+        let device = DeviceProfile { max_files_per_folder: Some(2), ..DeviceProfile::built_in()[0].clone() };
+        let mut entries = vec![
+            entry(output, "Rock/Live/01.mp3"),
+            entry(output, "Rock/Live/02.mp3"),
+            entry(output, "Rock/Live/03.mp3"),
+            entry(output, "Кино/04.mp3"),
+        ];
+
+        let warnings = device.fit(&mut entries, output, Transliteration::Phonetic);
+        let destinations: Vec<_> = entries.iter().map(|e| e.destination.clone()).collect();
+        assert_eq!(destinations, vec![
+            output.join("Rock - Live (1)/01.mp3"),
+            output.join("Rock - Live (1)/02.mp3"),
+            output.join("Rock - Live (2)/03.mp3"),
+            output.join("Kino/04.mp3"),
+        ]);
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_short_names_stay_unique() {
+        let output = Path::new("/out");
+        let device = DeviceProfile { short_names: true, ..DeviceProfile::new("Old player", FsProfile::Fat32) };
+        let mut entries = vec![
+            entry(output, "Greatest Hits 1/001 - First song.flac"),
+            entry(output, "Greatest Hits 2/001 - First song.flac"),
+            entry(output, "Greatest Hits 2/001 - First song again.flac"),
+        ];
+
+        device.fit(&mut entries, output, Transliteration::Phonetic);
+        let destinations: Vec<_> = entries.iter().map(|e| e.destination.clone()).collect();
+        assert_eq!(destinations, vec![
+            output.join("GREATEST/001-FIRS.FLA"),
+            output.join("GREATE~1/001-FIRS.FLA"),
+            output.join("GREATE~1/001-FI~1.FLA"),
+        ]);
+    }
+
+    #[test]
+    fn test_split_short_folders_get_their_own_parts() {
+        let output = Path::new("/out");
+        let device = DeviceProfile {
+            max_files_per_folder: Some(2),
+            short_names: true,
+            ..DeviceProfile::new("Old player", FsProfile::Fat32)
+        };
+        let mut entries: Vec<_> = ["Greatest Hits 1", "Greatest Hits 2"].iter()
+            .flat_map(|folder| (1..=3).map(move |n| format!("{}/{:02}.mp3", folder, n)))
+            .map(|relative| entry(output, &relative))
+            .collect();
+
+        let warnings = device.fit(&mut entries, output, Transliteration::Phonetic);
+        let mut sizes: HashMap<PathBuf, usize> = HashMap::new();
+        for entry in &entries {
+            *sizes.entry(entry.destination.parent().unwrap().to_path_buf()).or_default() += 1;
+        }
+        assert_eq!(sizes.len(), 4);
+        assert!(sizes.values().all(|size| *size <= 2), "{:?}", sizes);
+        assert!(warnings[0].contains("split into parts"), "{:?}", warnings);
+    }
+}
@@ -1,49 +1,519 @@
-use std::path::{PathBuf};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use natord::compare;
 use walkdir::WalkDir;
+use crate::dates::{CaptureDate, ClockOffsets};
+use crate::filter::FileFilter;
+use crate::tags::{AudioTags, TagField};
+use crate::template::{NameContext, Template};
 
 // --- File listing and renaming logic ---
-pub fn list_files_in_directory(path: &str, ext: &str) -> Result<Vec<PathBuf>, String> {
-    let ext_lower = ext.to_lowercase();
-    let mut entries: Vec<PathBuf> = WalkDir::new(path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|entry| entry.file_type().is_file())
-        .filter_map(|entry| {
-            let path = entry.path().to_path_buf();
-            let matches = path.extension()
-                .map(|e| e.to_string_lossy().to_lowercase() == ext_lower)
-                .unwrap_or(false);
-            if matches {
-                Some(path)
-            } else {
-                None
+pub fn list_files_in_directory(path: impl AsRef<Path>, ext: &str) -> Result<Vec<PathBuf>, String> {
+    list_files_in_directory_with_progress(path, &FileFilter::extensions(ext), |_| true)
+}
+
+/// Lists the files under `path` that `filter` picks up, calling `on_found`
+/// with the running count after each match. Returning `false` from it
+/// abandons the scan. Subfolders the filter skips are not entered.
+pub fn list_files_in_directory_with_progress(
+    path: impl AsRef<Path>,
+    filter: &FileFilter,
+    mut on_found: impl FnMut(usize) -> bool,
+) -> Result<Vec<PathBuf>, String> {
+    let root = path.as_ref();
+    let mut entries: Vec<PathBuf> = Vec::new();
+    let walker = WalkDir::new(root).into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_type().is_dir() || !filter.skips_dir(e.path()));
+    for entry in walker.filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if filter.matches(relative) {
+            entries.push(entry.path().to_path_buf());
+            if !on_found(entries.len()) {
+                return Err("Scan cancelled.".to_string());
             }
-        })
-        .collect();
+        }
+    }
 
-    entries.sort_by(|a, b| compare(a.to_string_lossy().as_ref(), b.to_string_lossy().as_ref()));
+    entries.sort_by(|a, b| natural(a, b));
     Ok(entries)
 }
 
-pub fn rename_files_with_leading_zeros(files: &[PathBuf], padding_zeros: usize, include_original_name: bool) -> Vec<String> {
-    files.iter()
-        .enumerate()
-        .map(|(i, path)| {
-            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
-            let index_str = format!("{:0width$}", i + 1, width=padding_zeros);
-            let ext = path.extension()
-                      .map(|e| format!(".{}", e.to_string_lossy()))
-                      .unwrap_or_default();
-            if include_original_name {
-                format!("{}_{}", index_str, file_name)
-            } else {
-                format!("{}{}", index_str, ext)
+/// Text for showing `path` to the user. A name that is not valid UTF-8 cannot
+/// be shown exactly: its undecodable bytes appear as `�` and the text is marked.
+/// Only for display; the engine keeps working with the original path.
+pub fn display_path(path: &Path) -> String {
+    match path.to_str() {
+        Some(text) => text.to_string(),
+        None => format!("{} (not UTF-8, shown approximately)", path.to_string_lossy()),
+    }
+}
+
+/// Turns the raw bytes of a name back into an `OsString`. Outside Unix,
+/// names are Unicode and bytes that are not UTF-8 are replaced.
+#[cfg(unix)]
+pub(crate) fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// File metadata, audio tags and capture dates of the files of one plan.
+/// Each is read the first time sorting, naming or the preview asks for it
+/// and kept, so no file is opened twice for the same thing.
+#[derive(Default)]
+pub struct FileDetails<'a> {
+    offsets: ClockOffsets,
+    files: HashMap<PathBuf, Details>,
+    keep_going: Option<Box<dyn FnMut() -> bool + 'a>>,
+    cancelled: bool,
+}
+
+#[derive(Debug, Default)]
+struct Details {
+    metadata: Option<Option<Metadata>>,
+    tags: Option<Result<Option<AudioTags>, String>>,
+    date: Option<Option<CaptureDate>>,
+}
+
+impl<'a> FileDetails<'a> {
+    /// Capture dates are corrected with `offsets`.
+    pub fn new(offsets: ClockOffsets) -> Self {
+        Self { offsets, ..Self::default() }
+    }
+
+    /// Calls `keep_going` before reading anything from a file. Once it
+    /// returns `false`, nothing more is read and every lookup comes back empty.
+    pub fn cancel_with(mut self, keep_going: impl FnMut() -> bool + 'a) -> Self {
+        self.keep_going = Some(Box::new(keep_going));
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    // Whether the next read may go ahead; split from `files` so it can be asked while an entry is borrowed.
+    fn proceed(keep_going: &mut Option<Box<dyn FnMut() -> bool + 'a>>, cancelled: &mut bool) -> bool {
+        *cancelled = *cancelled || keep_going.as_mut().is_some_and(|keep_going| !keep_going());
+        !*cancelled
+    }
+
+    pub fn metadata(&mut self, path: &Path) -> Option<&Metadata> {
+        let details = self.files.entry(path.to_path_buf()).or_default();
+        if details.metadata.is_none() && !Self::proceed(&mut self.keep_going, &mut self.cancelled) {
+            return None;
+        }
+        details.metadata.get_or_insert_with(|| fs::metadata(path).ok()).as_ref()
+    }
+
+    /// See [`AudioTags::read`].
+    pub fn tags(&mut self, path: &Path) -> Option<&AudioTags> {
+        self.probed_tags(path)?.as_ref().ok()?.as_ref()
+    }
+
+    /// Why the tags of `path` cannot be read, or `None` when they can.
+    pub fn tag_error(&mut self, path: &Path) -> Option<&str> {
+        self.probed_tags(path)?.as_ref().err().map(String::as_str)
+    }
+
+    fn probed_tags(&mut self, path: &Path) -> Option<&Result<Option<AudioTags>, String>> {
+        let details = self.files.entry(path.to_path_buf()).or_default();
+        if details.tags.is_none() && !Self::proceed(&mut self.keep_going, &mut self.cancelled) {
+            return None;
+        }
+        Some(details.tags.get_or_insert_with(|| AudioTags::probe(path)))
+    }
+
+    /// See [`CaptureDate::read_adjusted`].
+    pub fn date(&mut self, path: &Path) -> Option<&CaptureDate> {
+        let details = self.files.entry(path.to_path_buf()).or_default();
+        if details.date.is_none() && !Self::proceed(&mut self.keep_going, &mut self.cancelled) {
+            return None;
+        }
+        details.date.get_or_insert_with(|| CaptureDate::read_adjusted(path, &self.offsets)).as_ref()
+    }
+}
+
+/// Whether numbering runs across folders or starts again in each one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Numbering {
+    /// One sequence across every folder.
+    #[default]
+    Continuous,
+    /// The counter restarts in each folder; `{total}` counts the folder's files.
+    PerFolder,
+    /// Like `PerFolder`, with `{n}` prefixed by the folder's number, e.g. `1-01`, `1-02`, `2-01`.
+    Hierarchical,
+}
+
+impl Numbering {
+    pub const ALL: [Numbering; 3] = [Numbering::Continuous, Numbering::PerFolder, Numbering::Hierarchical];
+}
+
+impl fmt::Display for Numbering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Numbering::Continuous => "continuous",
+            Numbering::PerFolder => "per-folder",
+            Numbering::Hierarchical => "hierarchical",
+        })
+    }
+}
+
+impl FromStr for Numbering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Numbering::ALL
+            .into_iter()
+            .find(|numbering| numbering.to_string() == s)
+            .ok_or_else(|| format!("Unknown numbering: {} (expected continuous, per-folder or hierarchical)", s))
+    }
+}
+
+/// How sequence numbers are assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counter {
+    /// Number given to the first file, or to the last one when descending.
+    pub start: u64,
+    pub step: u64,
+    /// Numbers run from the highest down to `start`.
+    pub descending: bool,
+    pub numbering: Numbering,
+}
+
+impl Default for Counter {
+    fn default() -> Self {
+        Self { start: 1, step: 1, descending: false, numbering: Numbering::Continuous }
+    }
+}
+
+impl Counter {
+    /// Number of the file at `position` (from 0) out of `total`, or `None`
+    /// when it does not fit in a `u64`.
+    pub fn number(&self, position: usize, total: usize) -> Option<u64> {
+        let steps = if self.descending {
+            total.saturating_sub(position + 1)
+        } else {
+            position
+        };
+        self.step.checked_mul(steps as u64)?.checked_add(self.start)
+    }
+
+    /// Largest number handed out for `total` files, used for automatic padding.
+    pub fn highest(&self, total: usize) -> Option<u64> {
+        self.step.checked_mul(total.saturating_sub(1) as u64)?.checked_add(self.start)
+    }
+
+    /// Largest number handed out for `files`, taking per-folder restarts into account.
+    pub fn highest_for(&self, files: &[PathBuf]) -> Option<u64> {
+        match self.numbering {
+            Numbering::Continuous => self.highest(files.len()),
+            _ => self.highest(FolderGroups::of(files).sizes.into_iter().max().unwrap_or(0)),
+        }
+    }
+}
+
+// Folder of each file, numbered by first appearance, with its position inside that folder.
+struct FolderGroups {
+    positions: Vec<(usize, usize)>,
+    sizes: Vec<usize>,
+}
+
+impl FolderGroups {
+    fn of(files: &[PathBuf]) -> Self {
+        let mut folders: HashMap<&Path, usize> = HashMap::new();
+        let mut sizes = Vec::new();
+        let positions = files.iter()
+            .map(|path| {
+                let folder = path.parent().unwrap_or(Path::new(""));
+                let number = *folders.entry(folder).or_insert_with(|| {
+                    sizes.push(0);
+                    sizes.len() - 1
+                });
+                sizes[number] += 1;
+                (number, sizes[number] - 1)
+            })
+            .collect();
+        Self { positions, sizes }
+    }
+}
+
+/// What files are ordered by before they are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    /// File name only, so folders do not affect the order.
+    Name,
+    /// Full path, folder by folder.
+    #[default]
+    Path,
+    Modified,
+    Created,
+    Size,
+    /// Capture date of photos and videos, see [`crate::dates`].
+    Date,
+    /// Audio tags. Files missing the tag come after tagged ones, or before them when descending.
+    Artist,
+    Album,
+    /// Disc number, then track number within the disc.
+    Disc,
+    Track,
+    Title,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 11] = [
+        SortKey::Name, SortKey::Path, SortKey::Modified, SortKey::Created, SortKey::Size, SortKey::Date,
+        SortKey::Artist, SortKey::Album, SortKey::Disc, SortKey::Track, SortKey::Title,
+    ];
+
+    /// The audio tag this key reads, if any.
+    pub fn tag_field(&self) -> Option<TagField> {
+        match self {
+            SortKey::Artist => Some(TagField::Artist),
+            SortKey::Album => Some(TagField::Album),
+            SortKey::Disc => Some(TagField::Disc),
+            SortKey::Track => Some(TagField::Track),
+            SortKey::Title => Some(TagField::Title),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SortKey::Name => "name",
+            SortKey::Path => "path",
+            SortKey::Modified => "mtime",
+            SortKey::Created => "ctime",
+            SortKey::Size => "size",
+            SortKey::Date => "date",
+            SortKey::Artist => "artist",
+            SortKey::Album => "album",
+            SortKey::Disc => "disc",
+            SortKey::Track => "track",
+            SortKey::Title => "title",
+        })
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SortKey::ALL
+            .into_iter()
+            .find(|key| key.to_string() == s)
+            .ok_or_else(|| format!("Unknown sort key: {} (expected name, path, mtime, ctime, size, date, artist, album, disc, track or title)", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+fn natural(a: &Path, b: &Path) -> Ordering {
+    compare(a.to_string_lossy().as_ref(), b.to_string_lossy().as_ref())
+}
+
+// Sorts on a key computed once per file. The sort is stable and the files
+// arrive in path order, so equal keys always fall back to ascending path order.
+fn sort_by_cached<K: Ord>(files: &mut Vec<PathBuf>, descending: bool, mut key: impl FnMut(&Path) -> K) {
+    let mut keyed: Vec<(K, PathBuf)> = files.drain(..).map(|path| (key(&path), path)).collect();
+    keyed.sort_by(|(a, _), (b, _)| if descending { b.cmp(a) } else { a.cmp(b) });
+    files.extend(keyed.into_iter().map(|(_, path)| path));
+}
+
+/// Orders `files` for numbering. Ties are broken by the full path.
+pub fn sort_files(files: &mut Vec<PathBuf>, order: SortOrder, details: &mut FileDetails) {
+    files.sort_by(|a, b| natural(a, b));
+    let descending = order.descending;
+    match order.key {
+        SortKey::Path if descending => files.reverse(),
+        SortKey::Path => {}
+        SortKey::Name => {
+            files.sort_by(|a, b| {
+                let names = compare(
+                    a.file_name().unwrap_or_default().to_string_lossy().as_ref(),
+                    b.file_name().unwrap_or_default().to_string_lossy().as_ref(),
+                );
+                if descending { names.reverse() } else { names }
+            });
+        }
+        SortKey::Modified => sort_by_cached(files, descending, |p| details.metadata(p).and_then(|m| m.modified().ok())),
+        SortKey::Created => sort_by_cached(files, descending, |p| details.metadata(p).and_then(|m| m.created().ok())),
+        SortKey::Size => sort_by_cached(files, descending, |p| details.metadata(p).map(|m| m.len())),
+        SortKey::Date => sort_by_cached(files, descending, |p| {
+            let date = details.date(p).map(|date| date.time);
+            (date.is_none(), date)
+        }),
+        SortKey::Disc => sort_by_cached(files, descending, |p| {
+            let (disc, track) = details.tags(p).map_or((None, None), |tags| (tags.disc, tags.track));
+            (disc.is_none(), disc, track.is_none(), track)
+        }),
+        SortKey::Track => sort_by_cached(files, descending, |p| {
+            let track = details.tags(p).and_then(|tags| tags.track);
+            (track.is_none(), track)
+        }),
+        SortKey::Artist | SortKey::Album | SortKey::Title => {
+            let field = order.key.tag_field().expect("tag sort key");
+            sort_by_cached(files, descending, |p| {
+                let value = details.tags(p).and_then(|tags| tags.get(field)).map(|v| v.to_lowercase());
+                (value.is_none(), value)
+            });
+        }
+    }
+}
+
+/// How the files of several input folders are combined into one sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputMerge {
+    /// Each folder's files in turn, in the order the folders were given.
+    #[default]
+    Concatenate,
+    /// All files sorted together by the sort key.
+    Merge,
+    /// One file from each folder in turn until every folder is used up.
+    RoundRobin,
+}
+
+impl InputMerge {
+    pub const ALL: [InputMerge; 3] = [InputMerge::Concatenate, InputMerge::Merge, InputMerge::RoundRobin];
+}
+
+impl fmt::Display for InputMerge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputMerge::Concatenate => "concat",
+            InputMerge::Merge => "merge",
+            InputMerge::RoundRobin => "round-robin",
+        })
+    }
+}
+
+impl FromStr for InputMerge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        InputMerge::ALL
+            .into_iter()
+            .find(|merge| merge.to_string() == s)
+            .ok_or_else(|| format!("Unknown merge mode: {} (expected concat, merge or round-robin)", s))
+    }
+}
+
+/// Combines the file lists of several input folders, each already sorted,
+/// into one list. A file listed by more than one folder is kept once.
+pub fn merge_inputs(lists: Vec<Vec<PathBuf>>, merge: InputMerge, order: SortOrder, details: &mut FileDetails) -> Vec<PathBuf> {
+    let mut combined = match merge {
+        InputMerge::Concatenate | InputMerge::Merge => lists.into_iter().flatten().collect::<Vec<_>>(),
+        InputMerge::RoundRobin => {
+            let mut iters: Vec<_> = lists.into_iter().map(Vec::into_iter).collect();
+            let mut combined = Vec::new();
+            loop {
+                let before = combined.len();
+                combined.extend(iters.iter_mut().filter_map(Iterator::next));
+                if combined.len() == before {
+                    break combined;
+                }
             }
+        }
+    };
+    let mut seen = HashSet::new();
+    combined.retain(|path| seen.insert(path.clone()));
+    if merge == InputMerge::Merge {
+        sort_files(&mut combined, order, details);
+    }
+    combined
+}
+
+pub fn compute_auto_padding(total_files: usize) -> usize {
+    if total_files == 0 {
+        3 // default minimum padding
+    } else {
+        (total_files as f64).log10().ceil() as usize + 1
+    }
+}
+
+pub fn rename_files_with_leading_zeros(files: &[PathBuf], padding_zeros: usize, include_original_name: bool) -> Vec<OsString> {
+    // Counting from 1 in steps of 1 never gets past the number of files.
+    render_names(files, &Template::legacy(include_original_name), padding_zeros, Counter::default(), &mut FileDetails::default())
+        .expect("default counter does not overflow")
+}
+
+/// Sequence number of every file and the size of the run it is counted in,
+/// the whole list or its folder depending on the numbering. Fails when the
+/// numbers would not fit in a `u64`.
+pub fn sequence_numbers(files: &[PathBuf], counter: Counter) -> Result<Vec<(u64, usize)>, String> {
+    let groups = FolderGroups::of(files);
+    groups.positions.iter()
+        .enumerate()
+        .map(|(i, &(folder, position))| {
+            let (position, total) = match counter.numbering {
+                Numbering::Continuous => (i, files.len()),
+                _ => (position, groups.sizes[folder]),
+            };
+            let number = counter.number(position, total).ok_or_else(|| format!(
+                "Sequence numbers overflow: {} files starting at {} in steps of {} go past {}",
+                total, counter.start, counter.step, u64::MAX
+            ))?;
+            Ok((number, total))
         })
         .collect()
 }
 
+/// Names every file with `template`, numbering them in order with `counter`.
+pub fn render_names(
+    files: &[PathBuf],
+    template: &Template,
+    padding_zeros: usize,
+    counter: Counter,
+    details: &mut FileDetails,
+) -> Result<Vec<OsString>, String> {
+    let needs_metadata = template.needs_metadata();
+    let needs_tags = !template.tag_fields().is_empty();
+    let needs_date = template.needs_date();
+    let groups = FolderGroups::of(files);
+    let folder_width = groups.sizes.len().to_string().len();
+    let names = files.iter()
+        .zip(sequence_numbers(files, counter)?)
+        .enumerate()
+        .map(|(i, (path, (index, total)))| {
+            let metadata = if needs_metadata { details.metadata(path).cloned() } else { None };
+            let tags = if needs_tags { details.tags(path).cloned() } else { None };
+            let date = if needs_date { details.date(path).map(|date| date.time) } else { None };
+            let (folder, _) = groups.positions[i];
+            template.render(&NameContext {
+                path,
+                index,
+                folder: (counter.numbering == Numbering::Hierarchical)
+                    .then_some((folder as u64 + 1, folder_width)),
+                total: total as u64,
+                padding_zeros,
+                metadata: metadata.as_ref(),
+                tags: tags.as_ref(),
+                date,
+            })
+        })
+        .collect();
+    Ok(names)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -88,4 +558,100 @@ mod tests {
         assert_eq!(result[0], "01.mp3");
         assert_eq!(result[1], "02.mp3");
     }
+
+    #[test]
+    fn test_render_names_with_counter_start_step_and_descending() {
+        let files = vec![
+            PathBuf::from("a.mp3"),
+            PathBuf::from("b.mp3"),
+            PathBuf::from("c.mp3"),
+        ];
+        let template = Template::parse("{n} of {total:02}{ext}").unwrap();
+
+        let continued = render_names(&files, &template, 3, Counter { start: 47, ..Counter::default() }, &mut FileDetails::default()).unwrap();
+        assert_eq!(continued, vec!["047 of 03.mp3", "048 of 03.mp3", "049 of 03.mp3"]);
+
+        let reversed = render_names(&files, &template, 2, Counter { start: 10, step: 10, descending: true, ..Counter::default() }, &mut FileDetails::default()).unwrap();
+        assert_eq!(reversed, vec!["30 of 03.mp3", "20 of 03.mp3", "10 of 03.mp3"]);
+
+        let overflowing = Counter { start: u64::MAX, ..Counter::default() };
+        assert!(render_names(&files, &template, 2, overflowing, &mut FileDetails::default()).is_err());
+        assert_eq!(overflowing.highest(3), None);
+    }
+
+    #[test]
+    fn test_render_names_restarts_per_folder_and_hierarchically() {
+        let files = vec![
+            PathBuf::from("Disc 1/a.mp3"),
+            PathBuf::from("Disc 1/b.mp3"),
+            PathBuf::from("Disc 2/c.mp3"),
+        ];
+        let template = Template::parse("{n}{ext}").unwrap();
+
+        let per_folder = Counter { numbering: Numbering::PerFolder, ..Counter::default() };
+        assert_eq!(render_names(&files, &template, 2, per_folder, &mut FileDetails::default()).unwrap(), vec!["01.mp3", "02.mp3", "01.mp3"]);
+        assert_eq!(per_folder.highest_for(&files), Some(2));
+
+        let hierarchical = Counter { numbering: Numbering::Hierarchical, ..Counter::default() };
+        assert_eq!(render_names(&files, &template, 2, hierarchical, &mut FileDetails::default()).unwrap(), vec!["1-01.mp3", "1-02.mp3", "2-01.mp3"]);
+    }
+
+    #[test]
+    fn test_sort_files_by_name_and_size() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir(path.join("b")).unwrap();
+        fs::write(path.join("b").join("1.mp3"), "xxx").unwrap();
+        fs::write(path.join("2.mp3"), "x").unwrap();
+        fs::write(path.join("10.mp3"), "x").unwrap();
+
+        let mut files = list_files_in_directory(path.to_str().unwrap(), "mp3").unwrap();
+        sort_files(&mut files, SortOrder { key: SortKey::Name, descending: false }, &mut FileDetails::default());
+        assert_eq!(files, vec![path.join("b").join("1.mp3"), path.join("2.mp3"), path.join("10.mp3")]);
+
+        sort_files(&mut files, SortOrder { key: SortKey::Size, descending: true }, &mut FileDetails::default());
+        assert_eq!(files, vec![path.join("b").join("1.mp3"), path.join("2.mp3"), path.join("10.mp3")]);
+    }
+
+    #[test]
+    fn test_merge_inputs_concatenates_merges_and_interleaves() {
+        let lists = || vec![
+            vec![PathBuf::from("a/1.jpg"), PathBuf::from("a/5.jpg"), PathBuf::from("a/6.jpg")],
+            vec![PathBuf::from("b/2.jpg"), PathBuf::from("b/3.jpg")],
+        ];
+        let by_name = SortOrder { key: SortKey::Name, descending: false };
+        let names = |files: Vec<PathBuf>| files.iter().map(|f| f.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        let details = &mut FileDetails::default();
+
+        assert_eq!(names(merge_inputs(lists(), InputMerge::Concatenate, by_name, details)), ["a/1.jpg", "a/5.jpg", "a/6.jpg", "b/2.jpg", "b/3.jpg"]);
+        assert_eq!(names(merge_inputs(lists(), InputMerge::Merge, by_name, details)), ["a/1.jpg", "b/2.jpg", "b/3.jpg", "a/5.jpg", "a/6.jpg"]);
+        assert_eq!(names(merge_inputs(lists(), InputMerge::RoundRobin, by_name, details)), ["a/1.jpg", "b/2.jpg", "a/5.jpg", "b/3.jpg", "a/6.jpg"]);
+        assert_eq!(names(merge_inputs(vec![vec![PathBuf::from("a/1.jpg")]; 2], InputMerge::Concatenate, by_name, details)), ["a/1.jpg"]);
+    }
+
+    #[test]
+    fn test_render_names_keeps_separators_in_tags_out_of_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        crate::tags::write_test_flac(&path, &[("ARTIST", "AC/DC"), ("TITLE", "../Back\\in Black")]);
+        let template = Template::parse("{artist} - {title}{ext}").unwrap();
+
+        let names = render_names(&[path], &template, 2, Counter::default(), &mut FileDetails::default()).unwrap();
+        assert_eq!(names, vec!["AC_DC - .._Back_in Black.flac"]);
+    }
+
+    #[test]
+    fn test_sort_files_by_disc_and_track_puts_untagged_last() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        crate::tags::write_test_flac(&path.join("a.flac"), &[("DISCNUMBER", "2"), ("TRACKNUMBER", "1")]);
+        crate::tags::write_test_flac(&path.join("b.flac"), &[("DISCNUMBER", "1"), ("TRACKNUMBER", "10")]);
+        crate::tags::write_test_flac(&path.join("c.flac"), &[("DISCNUMBER", "1"), ("TRACKNUMBER", "2")]);
+        fs::write(path.join("d.flac"), "not audio").unwrap();
+
+        let mut files = list_files_in_directory(path.to_str().unwrap(), "flac").unwrap();
+        sort_files(&mut files, SortOrder { key: SortKey::Disc, descending: false }, &mut FileDetails::default());
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["c.flac", "b.flac", "a.flac", "d.flac"]);
+    }
 }
@@ -0,0 +1,151 @@
+//! Which files of an input folder are picked up.
+//!
+//! A file is picked up when its extension is one of the listed ones (or it
+//! has none and files without an extension are allowed), it matches at least
+//! one include pattern if any are given, and it matches no exclude pattern.
+//! Patterns are globs such as `**/Live/**` or `*sample*`, matched against the
+//! path relative to the input folder and ignoring case.
+//!
+//! Subfolders holding renamer output are never scanned: the job's output
+//! folder, and any folder containing a [`MANIFEST_NAME`] file left by a run.
+
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Marker file written into every output folder.
+pub const MANIFEST_NAME: &str = ".renamer-manifest";
+
+/// Marks `dir` as renamer output so later scans skip it.
+pub fn write_manifest(dir: &Path) -> io::Result<()> {
+    fs::write(
+        dir.join(MANIFEST_NAME),
+        "renamer-manifest 1\nThis folder holds renamer output; renamer skips it when scanning input folders.\n",
+    )
+}
+
+/// `path` made absolute without touching the file system, for comparing folders.
+pub fn absolute(path: &Path) -> PathBuf {
+    path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    /// Lowercase extensions without the dot; `*` accepts any extension.
+    extensions: Vec<String>,
+    no_extension: bool,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// Absolute folders that are never scanned.
+    skipped_dirs: Vec<PathBuf>,
+}
+
+/// Splits a list such as `mp3, .FLAC,m4a` into lowercase extensions without dots.
+pub fn parse_extensions(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty())
+        .collect()
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e.kind()))?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+impl FileFilter {
+    pub fn new(extensions: &[String], no_extension: bool, include: &[String], exclude: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            extensions: extensions.to_vec(),
+            no_extension,
+            include: if include.is_empty() { None } else { Some(build_globs(include)?) },
+            exclude: build_globs(exclude)?,
+            skipped_dirs: Vec::new(),
+        })
+    }
+
+    /// A filter on a comma-separated extension list only.
+    pub fn extensions(list: &str) -> Self {
+        Self {
+            extensions: parse_extensions(list),
+            no_extension: false,
+            include: None,
+            exclude: GlobSet::empty(),
+            skipped_dirs: Vec::new(),
+        }
+    }
+
+    /// Never scans `dir` or anything below it.
+    pub fn skip_dir(mut self, dir: &Path) -> Self {
+        self.skipped_dirs.push(absolute(dir));
+        self
+    }
+
+    /// Whether the subfolder `dir` is left out of the scan.
+    pub fn skips_dir(&self, dir: &Path) -> bool {
+        (!self.skipped_dirs.is_empty() && self.skipped_dirs.contains(&absolute(dir)))
+            || dir.join(MANIFEST_NAME).is_file()
+    }
+
+    /// Whether the file at `relative`, a path inside the input folder, is picked up.
+    pub fn matches(&self, relative: &Path) -> bool {
+        if relative.file_name().is_some_and(|name| name == MANIFEST_NAME) {
+            return false;
+        }
+        let extension_ok = match relative.extension() {
+            Some(ext) => {
+                let ext = ext.to_string_lossy().to_lowercase();
+                self.extensions.iter().any(|wanted| wanted == "*" || *wanted == ext)
+            }
+            None => self.no_extension,
+        };
+        extension_ok
+            && self.include.as_ref().is_none_or(|include| include.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+
+    /// Human-readable description for "nothing found" messages.
+    pub fn describe(&self) -> String {
+        let mut kinds: Vec<String> = self.extensions.iter().map(|ext| format!(".{}", ext)).collect();
+        if self.no_extension {
+            kinds.push("no extension".to_string());
+        }
+        kinds.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extensions_and_globs() {
+        let filter = FileFilter::new(
+            &parse_extensions("mp3, .FLAC"),
+            true,
+            &[],
+            &["**/Live/**".to_string(), "*sample*".to_string()],
+        ).unwrap();
+
+        assert!(filter.matches(Path::new("Album/01.mp3")));
+        assert!(filter.matches(Path::new("Album/02.flac")));
+        assert!(filter.matches(Path::new("README")));
+        assert!(!filter.matches(Path::new("Album/cover.jpg")));
+        assert!(!filter.matches(Path::new("Live/01.mp3")));
+        assert!(!filter.matches(Path::new("Album/live/01.mp3")));
+        assert!(!filter.matches(Path::new("Album/Sample-03.mp3")));
+
+        let only_disc_one = FileFilter::new(&parse_extensions("mp3"), false, &["Disc 1/*".to_string()], &[]).unwrap();
+        assert!(only_disc_one.matches(Path::new("Disc 1/a.mp3")));
+        assert!(!only_disc_one.matches(Path::new("Disc 2/a.mp3")));
+        assert!(FileFilter::new(&[], false, &["[".to_string()], &[]).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use crate::file_ops::display_path;
+use crate::job::PlannedRename;
+
+/// A file parked under a temporary name in its own folder.
+#[derive(Debug)]
+pub struct Staged {
+    pub source: PathBuf,
+    pub temporary: PathBuf,
+    pub destination: PathBuf,
+}
+
+fn temporary_name(source: &Path, index: usize) -> PathBuf {
+    let dir = source.parent().unwrap_or(Path::new(""));
+    let mut attempt = 0;
+    loop {
+        let candidate = dir.join(format!(".renamer-{}-{}-{}.tmp", process::id(), index, attempt));
+        if !candidate.exists() {
+            return candidate;
+        }
+        attempt += 1;
+    }
+}
+
+/// First phase: moves every file that changes name out of the way.
+///
+/// Once all sources are parked, no destination can clash with a file that is
+/// still waiting to be renamed, so chains and cycles resolve themselves.
+/// If parking fails, the files already parked are put back; the error says
+/// whether that worked.
+pub fn stage(entries: &[PlannedRename]) -> Result<Vec<Staged>, String> {
+    let mut staged = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.source == entry.destination {
+            continue;
+        }
+        let temporary = temporary_name(&entry.source, i);
+        if let Err(e) = fs::rename(&entry.source, &temporary) {
+            let left = restore(&staged, 0);
+            let outcome = if left.is_empty() { "no file was renamed".to_string() } else { restore_note(&left) };
+            return Err(format!("{}: {} ({})", entry.source.to_string_lossy(), e, outcome));
+        }
+        staged.push(Staged {
+            source: entry.source.clone(),
+            temporary,
+            destination: entry.destination.clone(),
+        });
+    }
+    Ok(staged)
+}
+
+/// Second phase for one file: gives it its final name, refusing to overwrite.
+pub fn finish(staged: &Staged) -> Result<(), String> {
+    if fs::symlink_metadata(&staged.destination).is_ok() {
+        return Err(format!("{} already exists", staged.destination.to_string_lossy()));
+    }
+    fs::rename(&staged.temporary, &staged.destination)
+        .map_err(|e| format!("{}: {}", staged.destination.to_string_lossy(), e))
+}
+
+/// Undoes both phases: the first `finished` files go back to their temporary
+/// name, then every parked file goes back to its original name. Returns each
+/// file that could not be put back, with the path it was left at.
+pub fn restore(staged: &[Staged], finished: usize) -> Vec<(PathBuf, PathBuf)> {
+    let mut left = Vec::new();
+    let mut stuck = vec![false; staged.len()];
+    for (i, entry) in staged[..finished].iter().enumerate().rev() {
+        if fs::rename(&entry.destination, &entry.temporary).is_err() {
+            stuck[i] = true;
+            left.push((entry.source.clone(), entry.destination.clone()));
+        }
+    }
+    for (entry, stuck) in staged.iter().zip(stuck).rev() {
+        if !stuck && fs::rename(&entry.temporary, &entry.source).is_err() {
+            left.push((entry.source.clone(), entry.temporary.clone()));
+        }
+    }
+    left
+}
+
+/// Describes the outcome of [`restore`] for an error message.
+pub fn restore_note(left: &[(PathBuf, PathBuf)]) -> String {
+    if left.is_empty() {
+        return "all files were restored".to_string();
+    }
+    let files: Vec<String> = left.iter()
+        .map(|(source, current)| format!("{} is left as {}", display_path(source), display_path(current)))
+        .collect();
+    format!("could not put every file back: {}", files.join("; "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_stage_and_finish_resolve_cycle() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("001.mp3");
+        let b = dir.path().join("002.mp3");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let entries = vec![
+            PlannedRename { source: a.clone(), destination: b.clone(), notes: Vec::new(), tag_update: None },
+            PlannedRename { source: b.clone(), destination: a.clone(), notes: Vec::new(), tag_update: None },
+        ];
+        let staged = stage(&entries).unwrap();
+        for entry in &staged {
+            finish(entry).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&a).unwrap(), "b");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_restore_after_failed_finish() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.mp3");
+        let b = dir.path().join("b.mp3");
+        let blocker = dir.path().join("blocker.mp3");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        fs::write(&blocker, "x").unwrap();
+
+        let entries = vec![
+            PlannedRename { source: a.clone(), destination: dir.path().join("001.mp3"), notes: Vec::new(), tag_update: None },
+            PlannedRename { source: b.clone(), destination: blocker.clone(), notes: Vec::new(), tag_update: None },
+        ];
+        let staged = stage(&entries).unwrap();
+        finish(&staged[0]).unwrap();
+        assert!(finish(&staged[1]).is_err());
+        assert!(restore(&staged, 1).is_empty());
+
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+        assert!(!dir.path().join("001.mp3").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_restore_reports_files_it_cannot_put_back() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a.mp3");
+        fs::write(&a, "a").unwrap();
+
+        let entries = vec![
+            PlannedRename { source: a.clone(), destination: dir.path().join("001.mp3"), notes: Vec::new(), tag_update: None },
+        ];
+        let staged = stage(&entries).unwrap();
+        fs::remove_file(&staged[0].temporary).unwrap();
+
+        let left = restore(&staged, 0);
+        assert_eq!(left, vec![(a, staged[0].temporary.clone())]);
+        assert!(restore_note(&left).starts_with("could not put every file back: "));
+    }
+}
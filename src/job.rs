@@ -0,0 +1,632 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use async_stream::stream;
+use futures::Stream;
+use crate::control::RunControl;
+use crate::dates::{CaptureDate, ClockOffsets};
+use crate::device::DeviceProfile;
+use crate::filter::{absolute, parse_extensions, write_manifest, FileFilter};
+use crate::conflicts::{check_plan, PlanReport};
+use crate::in_place;
+use crate::journal::{JournalWriter, RunMode};
+use crate::transfer::{transfer_with_control, TransferMode};
+use crate::file_ops::{compute_auto_padding, display_path, Counter, FileDetails, InputMerge, list_files_in_directory_with_progress, merge_inputs, render_names, sequence_numbers, sort_files, SortKey, SortOrder};
+use crate::tags::{missing_fields, TagUpdate, TagWriting};
+use crate::sanitize::{FsProfile, Normalization};
+use crate::template::Template;
+use crate::translit::Transliteration;
+
+/// Names are not cut below this many characters to fit a path limit; the
+/// folder path is reported as too long instead.
+const MIN_NAME_ROOM: usize = 16;
+
+/// Everything needed to number the files of one or more input folders.
+#[derive(Debug, Clone)]
+pub struct RenameJob {
+    inputs: Vec<PathBuf>,
+    input_merge: InputMerge,
+    output: PathBuf,
+    extensions: Vec<String>,
+    no_extension: bool,
+    include_patterns: Vec<String>,
+    exclude_patterns: Vec<String>,
+    padding_zeros: Option<usize>,
+    include_original_name: bool,
+    template: Option<String>,
+    counter: Counter,
+    sort: SortOrder,
+    preserve_tree: bool,
+    in_place: bool,
+    transfer_mode: TransferMode,
+    journal_dir: Option<PathBuf>,
+    write_tags: Option<TagWriting>,
+    clock_offsets: ClockOffsets,
+    transliteration: Option<Transliteration>,
+    fs_profile: Option<FsProfile>,
+    normalization: Normalization,
+    device: Option<DeviceProfile>,
+}
+
+/// One source file and the path it will be written to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedRename {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Warnings to show next to the file in the preview, such as missing tags.
+    pub notes: Vec<String>,
+    /// Tags written into the destination once it is in place.
+    pub tag_update: Option<TagUpdate>,
+}
+
+/// The full list of renames a job would perform, computed without touching any file.
+#[derive(Debug, Clone, Default)]
+pub struct RenamePlan {
+    pub output_dir: PathBuf,
+    pub padding_zeros: usize,
+    /// Files are renamed inside their own folder instead of being copied to `output_dir`.
+    pub in_place: bool,
+    pub transfer_mode: TransferMode,
+    /// Folder receiving the undo journal of the run, if any.
+    pub journal_dir: Option<PathBuf>,
+    pub entries: Vec<PlannedRename>,
+    /// Problems worth showing that do not stop the run.
+    pub warnings: Vec<String>,
+    /// The target tells apart names that differ only in case or Unicode form.
+    /// Only known for ext4 targets; otherwise such names are reported as conflicts.
+    pub case_sensitive: bool,
+}
+
+/// Events emitted while a plan is executed.
+#[derive(Debug, Clone)]
+pub enum Progress {
+    /// `done` out of `total` files have been written; the last one went to `destination`.
+    Renamed { done: usize, total: usize, destination: PathBuf },
+    /// The run is over, successfully with the written paths or with an error.
+    Finished(Result<Vec<PathBuf>, String>),
+    /// The run was stopped through its [`RunControl`]. `completed` lists the
+    /// destinations written before that; they are in the journal and can be undone.
+    Cancelled { completed: Vec<PathBuf> },
+}
+
+impl RenameJob {
+    pub fn new(input: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        Self {
+            inputs: vec![input.into()],
+            input_merge: InputMerge::Concatenate,
+            output: output.into(),
+            extensions: vec!["mp3".into()],
+            no_extension: false,
+            include_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+            padding_zeros: None,
+            include_original_name: false,
+            template: None,
+            counter: Counter::default(),
+            sort: SortOrder::default(),
+            preserve_tree: false,
+            in_place: false,
+            transfer_mode: TransferMode::Copy,
+            journal_dir: None,
+            write_tags: None,
+            clock_offsets: ClockOffsets::default(),
+            transliteration: None,
+            fs_profile: None,
+            normalization: Normalization::Unchanged,
+            device: None,
+        }
+    }
+
+    /// Extensions of the files to pick up, comma-separated, with or without
+    /// the leading dot, e.g. `mp3,flac,m4a`. `*` accepts any extension.
+    pub fn extension(mut self, ext: impl AsRef<str>) -> Self {
+        self.extensions = parse_extensions(ext.as_ref());
+        self
+    }
+
+    /// Also picks up files that have no extension.
+    pub fn no_extension(mut self, include: bool) -> Self {
+        self.no_extension = include;
+        self
+    }
+
+    /// Only picks up files matching this glob, or any other include pattern.
+    /// See [`crate::filter`] for how patterns are matched.
+    pub fn include_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.include_patterns.push(pattern.into());
+        self
+    }
+
+    /// Skips files matching this glob.
+    pub fn exclude_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude_patterns.push(pattern.into());
+        self
+    }
+
+    /// Fixed number of leading zeros. Without it the padding is derived from the file count.
+    pub fn padding_zeros(mut self, padding_zeros: usize) -> Self {
+        self.padding_zeros = Some(padding_zeros);
+        self
+    }
+
+    pub fn include_original_name(mut self, include: bool) -> Self {
+        self.include_original_name = include;
+        self
+    }
+
+    /// Naming template, see [`crate::template`]. Replaces the index/original-name choice.
+    pub fn template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    /// Start, step and direction of the sequence numbers. The step must be 1 or more.
+    pub fn counter(mut self, counter: Counter) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// Order in which files are numbered. Defaults to the full path.
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Recreates each file's subfolder, relative to the input folder, inside the output folder.
+    pub fn preserve_tree(mut self, preserve: bool) -> Self {
+        self.preserve_tree = preserve;
+        self
+    }
+
+    /// Renames files in their own folder; the output folder is ignored.
+    pub fn in_place(mut self, in_place: bool) -> Self {
+        self.in_place = in_place;
+        self
+    }
+
+    /// How files reach the output folder. Ignored for in-place jobs.
+    pub fn transfer_mode(mut self, mode: TransferMode) -> Self {
+        self.transfer_mode = mode;
+        self
+    }
+
+    /// Records every completed operation in a journal in this folder so the run can be undone.
+    pub fn journal_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.journal_dir = Some(dir.into());
+        self
+    }
+
+    /// Writes each file's sequence number into the track-number tag of its
+    /// destination. Needs a transfer mode that gives the destination its own
+    /// data and leaves the source as it was: copy or reflink. Files whose
+    /// tags cannot be read are copied untagged, with a note in the plan.
+    pub fn write_tags(mut self, writing: TagWriting) -> Self {
+        self.write_tags = Some(writing);
+        self
+    }
+
+    /// Corrects capture dates per folder or camera before sorting and naming.
+    pub fn clock_offsets(mut self, offsets: ClockOffsets) -> Self {
+        self.clock_offsets = offsets;
+        self
+    }
+
+    /// Spells the new names in ASCII with `scheme`. In names that are not
+    /// valid UTF-8, the bytes that cannot be read become `_`.
+    pub fn transliterate(mut self, scheme: Transliteration) -> Self {
+        self.transliteration = Some(scheme);
+        self
+    }
+
+    /// Adjusts new names, and subfolders kept with `preserve_tree`, so that
+    /// the file system of the output target accepts them.
+    pub fn fs_profile(mut self, profile: FsProfile) -> Self {
+        self.fs_profile = Some(profile);
+        self
+    }
+
+    /// Brings the new names into one Unicode form. Defaults to leaving them as rendered.
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Fits the output to the limits of `device`, moving files into other
+    /// folders where needed. Also sets the file system profile and, for
+    /// devices that need ASCII names, phonetic transliteration unless a
+    /// scheme is chosen afterwards.
+    pub fn device(mut self, device: DeviceProfile) -> Self {
+        self.fs_profile = Some(device.fs);
+        if device.ascii_only {
+            self.transliteration.get_or_insert_default();
+        }
+        self.device = Some(device);
+        self
+    }
+
+    /// Adds another input folder whose files join the same numbered sequence.
+    pub fn add_input(mut self, input: impl Into<PathBuf>) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    /// How the files of several input folders are combined. Defaults to one folder after another.
+    pub fn input_merge(mut self, merge: InputMerge) -> Self {
+        self.input_merge = merge;
+        self
+    }
+
+    /// The first input folder.
+    pub fn input(&self) -> &Path {
+        &self.inputs[0]
+    }
+
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Scans the input folder and computes every destination name.
+    pub fn plan(&self) -> Result<RenamePlan, String> {
+        self.plan_with_progress(|_| true)
+    }
+
+    /// Like [`RenameJob::plan`], reporting the number of files found so far.
+    /// Returning `false` from `on_found` abandons the scan. Once every file
+    /// is found, `on_found` is called with the final count before each file's
+    /// tags, dates or metadata are read, so the scan can still be abandoned.
+    pub fn plan_with_progress(&self, mut on_found: impl FnMut(usize) -> bool) -> Result<RenamePlan, String> {
+        let template = match &self.template {
+            Some(source) => Template::parse(source).map_err(|e| format!("Invalid template: {}", e))?,
+            None => Template::legacy(self.include_original_name),
+        };
+        if self.counter.step == 0 {
+            return Err("The step between sequence numbers must be 1 or more; with 0 every file gets the same number.".to_string());
+        }
+        if self.device.is_some() && self.in_place {
+            return Err("Device profiles arrange the output folder; turn off in-place renaming.".to_string());
+        }
+        if self.write_tags.is_some() {
+            if self.in_place {
+                return Err("Tags can only be written into copies; turn off in-place renaming.".to_string());
+            }
+            if matches!(self.transfer_mode, TransferMode::HardLink | TransferMode::Symlink) {
+                return Err(format!("Tags cannot be written in {} mode: the destination shares its data with the source.", self.transfer_mode));
+            }
+            if self.transfer_mode == TransferMode::Move {
+                return Err("Tags cannot be written in move mode: the moved file is the only copy, and undo would not bring back its old tags.".to_string());
+            }
+        }
+        let mut filter = FileFilter::new(&self.extensions, self.no_extension, &self.include_patterns, &self.exclude_patterns)?;
+        let mut warnings = Vec::new();
+        if !self.in_place {
+            filter = filter.skip_dir(&self.output);
+            let output = absolute(&self.output);
+            for input in &self.inputs {
+                if output != absolute(input) && output.starts_with(absolute(input)) {
+                    warnings.push(format!(
+                        "The output folder {} is inside the input folder {}; it is left out of the scan.",
+                        self.output.to_string_lossy(),
+                        input.to_string_lossy(),
+                    ));
+                }
+            }
+        }
+        let mut lists = Vec::new();
+        let mut found_before = 0;
+        for input in &self.inputs {
+            let files = list_files_in_directory_with_progress(
+                input,
+                &filter,
+                |found| on_found(found_before + found),
+            )?;
+            found_before += files.len();
+            lists.push(files);
+        }
+        let cancelled = || Err("Scan cancelled.".to_string());
+        let mut details = FileDetails::new(self.clock_offsets.resolved(&self.inputs))
+            .cancel_with(move || on_found(found_before));
+        for files in &mut lists {
+            sort_files(files, self.sort, &mut details);
+        }
+        let files = merge_inputs(lists, self.input_merge, self.sort, &mut details);
+        if details.is_cancelled() {
+            return cancelled();
+        }
+        let numbers = sequence_numbers(&files, self.counter)?;
+        let padding_zeros = self.padding_zeros
+            .unwrap_or_else(|| compute_auto_padding(self.counter.highest_for(&files).unwrap_or(u64::MAX) as usize));
+        let mut new_names = render_names(&files, &template, padding_zeros, self.counter, &mut details)?;
+        if details.is_cancelled() {
+            return cancelled();
+        }
+        if self.transliteration.is_some() || self.normalization != Normalization::Unchanged {
+            for name in &mut new_names {
+                let text = match (name.to_str(), self.transliteration) {
+                    (Some(text), Some(scheme)) => scheme.apply(text),
+                    (Some(text), None) => text.to_string(),
+                    // Bytes in an unknown encoding have no letters to transliterate,
+                    // but the name must still come out ASCII.
+                    (None, Some(scheme)) => scheme.apply(&name.to_string_lossy().replace(char::REPLACEMENT_CHARACTER, "_")),
+                    (None, None) => continue,
+                };
+                *name = self.normalization.apply(&text).into();
+            }
+        }
+        // Separators are already gone; these would still name the folder itself or its parent.
+        if let Some((source, name)) = files.iter().zip(&new_names).find(|(_, name)| matches!(name.to_str(), Some("" | "." | ".."))) {
+            return Err(format!(
+                "The template gives {} the name '{}', which cannot be used as a file name.",
+                display_path(source),
+                name.to_string_lossy(),
+            ));
+        }
+        let mut tag_fields = template.tag_fields();
+        if let Some(field) = self.sort.key.tag_field().filter(|f| !tag_fields.contains(f)) {
+            tag_fields.push(field);
+        }
+        let uses_date = template.needs_date() || self.sort.key == SortKey::Date;
+
+        let mut entries: Vec<PlannedRename> = files.into_iter()
+            .zip(new_names)
+            .zip(numbers)
+            .map(|((source, mut new_name), (index, total))| {
+                let mut notes = Vec::new();
+                let mut folder = if self.preserve_tree && !self.in_place {
+                    self.relative_folder(&source).to_path_buf()
+                } else {
+                    PathBuf::new()
+                };
+                if let Some(profile) = self.fs_profile {
+                    let (sanitized, changed) = profile.sanitize_folder(&folder);
+                    if changed {
+                        notes.push(format!("folder names adjusted for {}", profile));
+                    }
+                    folder = sanitized;
+                    let mut limit = profile.name_limit();
+                    if !self.in_place {
+                        let taken = profile.path_units(&folder) + usize::from(!folder.as_os_str().is_empty());
+                        match profile.path_limit().checked_sub(taken).filter(|room| *room >= MIN_NAME_ROOM) {
+                            Some(room) => limit = limit.min(room),
+                            None => notes.push(format!("folder path is too long for {}", profile)),
+                        }
+                    }
+                    let sanitized = profile.sanitize_name(&new_name, limit);
+                    if !sanitized.changes.is_empty() {
+                        notes.push(format!("name adjusted for {}: {}", profile, sanitized.changes.join(", ")));
+                    }
+                    new_name = sanitized.name;
+                }
+                let destination = if self.in_place {
+                    source.with_file_name(new_name)
+                } else {
+                    self.output.join(folder).join(new_name)
+                };
+                if !tag_fields.is_empty() {
+                    notes.extend(missing_fields(details.tags(&source), &tag_fields));
+                }
+                if uses_date {
+                    notes.extend(CaptureDate::note(details.date(&source)));
+                }
+                let tag_update = self.write_tags.and_then(|writing| match details.tag_error(&source).map(str::to_string) {
+                    Some(error) => {
+                        notes.push(format!("tags cannot be written: {}", error));
+                        None
+                    }
+                    None => Some(TagUpdate::new(writing, &source, details.tags(&source), index, total)),
+                });
+                PlannedRename { source, destination, notes, tag_update }
+            })
+            .collect();
+        if details.is_cancelled() {
+            return cancelled();
+        }
+        if let Some(device) = &self.device {
+            warnings.extend(device.fit(&mut entries, &self.output, self.transliteration.unwrap_or_default()));
+        }
+
+        Ok(RenamePlan {
+            output_dir: if self.in_place { self.input().to_path_buf() } else { self.output.clone() },
+            padding_zeros,
+            in_place: self.in_place,
+            transfer_mode: self.transfer_mode,
+            journal_dir: self.journal_dir.clone(),
+            entries,
+            warnings,
+            case_sensitive: self.fs_profile == Some(FsProfile::Ext4),
+        })
+    }
+
+    // Folder of `source` relative to the input folder it came from, empty for top-level files.
+    fn relative_folder<'a>(&self, source: &'a Path) -> &'a Path {
+        source.parent()
+            .and_then(|parent| self.inputs.iter().find_map(|input| parent.strip_prefix(input).ok()))
+            .unwrap_or(Path::new(""))
+    }
+
+    /// Plans and executes the job in one go.
+    pub fn run(&self) -> impl Stream<Item = Progress> + use<> {
+        let plan = self.plan();
+        stream! {
+            let plan = match plan {
+                Ok(plan) => plan,
+                Err(e) => {
+                    yield Progress::Finished(Err(e));
+                    return;
+                }
+            };
+            for await event in plan.execute() {
+                yield event;
+            }
+        }
+    }
+}
+
+impl RenamePlan {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Reports collisions and unwritable targets without touching any file.
+    pub fn check(&self) -> PlanReport {
+        check_plan(self)
+    }
+
+    /// Transfers every source file to its destination, reporting progress after each file.
+    /// In-place plans are renamed instead; see [`RenamePlan::execute_in_place`].
+    pub fn execute(self) -> impl Stream<Item = Progress> {
+        self.execute_with_control(RunControl::default())
+    }
+
+    /// Like [`RenamePlan::execute`], but can be paused and cancelled through `control`.
+    pub fn execute_with_control(self, control: RunControl) -> impl Stream<Item = Progress> {
+        stream! {
+            let total_files = self.entries.len();
+            if total_files == 0 {
+                yield Progress::Finished(Err("No files found to rename.".to_string()));
+                return;
+            }
+
+            if self.in_place {
+                for await event in self.execute_in_place(control) {
+                    yield event;
+                }
+                return;
+            }
+
+            if let Err(e) = fs::create_dir_all(&self.output_dir).and_then(|()| write_manifest(&self.output_dir)) {
+                yield Progress::Finished(Err(e.to_string()));
+                return;
+            }
+
+            let mut journal = match self.open_journal(RunMode::Transfer(self.transfer_mode)) {
+                Ok(journal) => journal,
+                Err(e) => {
+                    yield Progress::Finished(Err(e));
+                    return;
+                }
+            };
+
+            let mut written = Vec::new();
+            for (i, entry) in self.entries.into_iter().enumerate() {
+                let result = if control.proceed() {
+                    // Subfolders only exist in the plan when the tree is preserved or a device profile splits the output.
+                    entry.destination.parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|()| transfer_with_control(self.transfer_mode, &entry.source, &entry.destination, &control))
+                } else {
+                    Err(io::ErrorKind::Interrupted.into())
+                };
+                if let Err(e) = result {
+                    if let Some(journal) = journal.filter(|_| written.is_empty()) {
+                        journal.discard();
+                    }
+                    if control.is_cancelled() {
+                        yield Progress::Cancelled { completed: written };
+                    } else {
+                        yield Progress::Finished(Err(format!("{}: {}", entry.source.to_string_lossy(), e)));
+                    }
+                    return;
+                }
+                // The copy is journaled even if its tags could not be written, so it can be undone.
+                let tagged = entry.tag_update.as_ref().map_or(Ok(()), |update| update.write_to(&entry.destination));
+                if let Some(Err(e)) = journal.as_mut().map(|j| j.record(&entry.source, &entry.destination)) {
+                    yield Progress::Finished(Err(format!("Could not update the undo journal: {}", e)));
+                    return;
+                }
+                if let Err(e) = tagged {
+                    yield Progress::Finished(Err(format!("{}: could not write tags: {}", entry.destination.to_string_lossy(), e)));
+                    return;
+                }
+
+                written.push(entry.destination.clone());
+                yield Progress::Renamed { done: i + 1, total: total_files, destination: entry.destination };
+            }
+
+            yield Progress::Finished(Ok(written));
+        }
+    }
+
+    /// Renames files in their own folder in two phases, first to temporary
+    /// names and then to their final names, so that one file may take the name
+    /// another one is giving up. Any failure or cancel puts every file back.
+    fn execute_in_place(self, control: RunControl) -> impl Stream<Item = Progress> {
+        stream! {
+            let total_files = self.entries.len();
+            let unchanged = total_files - self.entries.iter().filter(|e| e.source != e.destination).count();
+
+            let mut journal = match self.open_journal(RunMode::InPlace) {
+                Ok(journal) => journal,
+                Err(e) => {
+                    yield Progress::Finished(Err(e));
+                    return;
+                }
+            };
+
+            let staged = match in_place::stage(&self.entries) {
+                Ok(staged) => staged,
+                Err(e) => {
+                    if let Some(journal) = journal {
+                        journal.discard();
+                    }
+                    yield Progress::Finished(Err(e));
+                    return;
+                }
+            };
+
+            for (i, entry) in staged.iter().enumerate() {
+                if !control.proceed() {
+                    let left = in_place::restore(&staged, i);
+                    if let Some(journal) = journal {
+                        journal.discard();
+                    }
+                    if left.is_empty() {
+                        yield Progress::Cancelled { completed: Vec::new() };
+                    } else {
+                        yield Progress::Finished(Err(format!("Renaming was cancelled, but {}", in_place::restore_note(&left))));
+                    }
+                    return;
+                }
+                if let Err(e) = in_place::finish(entry) {
+                    let left = in_place::restore(&staged, i);
+                    if let Some(journal) = journal {
+                        journal.discard();
+                    }
+                    yield Progress::Finished(Err(format!("{} ({})", e, in_place::restore_note(&left))));
+                    return;
+                }
+                yield Progress::Renamed {
+                    done: unchanged + i + 1,
+                    total: total_files,
+                    destination: entry.destination.clone(),
+                };
+            }
+
+            // Only a complete in-place run leaves files renamed, so it is journaled at the end.
+            if let Some(writer) = journal.as_mut() {
+                for entry in &staged {
+                    if let Err(e) = writer.record(&entry.source, &entry.destination) {
+                        yield Progress::Finished(Err(format!("Could not update the undo journal: {}", e)));
+                        return;
+                    }
+                }
+            }
+
+            yield Progress::Finished(Ok(self.entries.into_iter().map(|e| e.destination).collect()));
+        }
+    }
+
+    fn open_journal(&self, mode: RunMode) -> Result<Option<JournalWriter>, String> {
+        self.journal_dir.as_ref()
+            .map(|dir| JournalWriter::create(dir, mode))
+            .transpose()
+            .map_err(|e| format!("Could not create the undo journal: {}", e))
+    }
+}
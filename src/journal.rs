@@ -0,0 +1,466 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::file_ops::bytes_to_os_string;
+use crate::in_place;
+use crate::job::PlannedRename;
+use crate::transfer::{transfer, TransferMode};
+
+const HEADER: &str = "renamer-journal 1";
+const EXTENSION: &str = "journal";
+/// Bytes hashed at each end of a file for its fingerprint.
+const SAMPLE: u64 = 64 * 1024;
+
+/// What a run did to its files, which decides how it is undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    Transfer(TransferMode),
+    InPlace,
+}
+
+impl fmt::Display for RunMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunMode::Transfer(mode) => mode.fmt(f),
+            RunMode::InPlace => f.write_str("in-place"),
+        }
+    }
+}
+
+impl FromStr for RunMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in-place" => Ok(RunMode::InPlace),
+            other => other.parse().map(RunMode::Transfer),
+        }
+    }
+}
+
+/// Size, modification time and a hash of the first and last 64 KiB of a
+/// written file, used to notice later edits. An edit that keeps the size and
+/// time and only touches the middle of a larger file goes unnoticed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub modified_nanos: u128,
+    pub content: u64,
+}
+
+impl Fingerprint {
+    fn of(path: &Path, mode: RunMode) -> io::Result<Self> {
+        // Undoing a symlink removes the link itself, so that is what has to be unchanged.
+        let meta = if mode == RunMode::Transfer(TransferMode::Symlink) {
+            fs::symlink_metadata(path)?
+        } else {
+            fs::metadata(path)?
+        };
+        let modified_nanos = meta.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let content = if meta.is_symlink() {
+            fnv1a(FNV_OFFSET, fs::read_link(path)?.as_os_str().as_encoded_bytes())
+        } else {
+            sample_hash(path, meta.len())?
+        };
+        Ok(Self { size: meta.len(), modified_nanos, content })
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+// FNV-1a: small and, unlike the std hashers, the same in every build, which
+// journals written by one version and undone by another rely on.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn sample_hash(path: &Path, size: u64) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buffer = Vec::new();
+    (&mut file).take(SAMPLE).read_to_end(&mut buffer)?;
+    let mut hash = fnv1a(FNV_OFFSET, &buffer);
+    if size > SAMPLE {
+        buffer.clear();
+        file.seek(SeekFrom::Start(size.saturating_sub(SAMPLE).max(SAMPLE)))?;
+        file.take(SAMPLE).read_to_end(&mut buffer)?;
+        hash = fnv1a(hash, &buffer);
+    }
+    Ok(hash)
+}
+
+/// One completed operation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub fingerprint: Fingerprint,
+}
+
+/// Every operation of one run, as read back from disk.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    pub path: PathBuf,
+    pub mode: RunMode,
+    /// Seconds since the Unix epoch.
+    pub started: u64,
+    pub entries: Vec<JournalEntry>,
+}
+
+/// Outcome of an undo.
+#[derive(Debug, Clone, Default)]
+pub struct UndoReport {
+    pub reverted: usize,
+    /// Files that were left alone, with the reason.
+    pub skipped: Vec<String>,
+}
+
+/// Where the front ends keep their journals.
+pub fn default_journal_dir() -> Option<PathBuf> {
+    dirs_next::data_dir().map(|dir| dir.join("renamer").join("journals"))
+}
+
+// Paths are written one per field, so tabs, newlines and `%` are escaped as
+// `%XX`. Names that are not valid UTF-8 have every non-ASCII byte escaped.
+fn encode_path(path: &Path) -> String {
+    let escaped = |byte: u8| matches!(byte, b'%' | b'\t' | b'\n' | b'\r');
+    let mut encoded = String::new();
+    match path.to_str() {
+        Some(text) => {
+            for c in text.chars() {
+                if c.is_ascii() && escaped(c as u8) {
+                    encoded.push_str(&format!("%{:02X}", c as u8));
+                } else {
+                    encoded.push(c);
+                }
+            }
+        }
+        None => {
+            for &byte in path.as_os_str().as_encoded_bytes() {
+                if escaped(byte) || byte >= 0x80 {
+                    encoded.push_str(&format!("%{:02X}", byte));
+                } else {
+                    encoded.push(byte as char);
+                }
+            }
+        }
+    }
+    encoded
+}
+
+fn decode_path(field: &str) -> Result<PathBuf, String> {
+    let mut bytes = Vec::new();
+    let raw = field.as_bytes();
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] == b'%' {
+            let hex = field.get(i + 1..i + 3).ok_or("truncated escape in journal")?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| "invalid escape in journal")?);
+            i += 3;
+        } else {
+            bytes.push(raw[i]);
+            i += 1;
+        }
+    }
+    Ok(PathBuf::from(bytes_to_os_string(bytes)))
+}
+
+fn format_entry(entry: &JournalEntry) -> String {
+    format!(
+        "op\t{}\t{}\t{:016x}\t{}\t{}\n",
+        entry.fingerprint.size,
+        entry.fingerprint.modified_nanos,
+        entry.fingerprint.content,
+        encode_path(&entry.source),
+        encode_path(&entry.destination),
+    )
+}
+
+/// Appends operations to a journal file as they complete, so that even an
+/// interrupted run can be undone.
+#[derive(Debug)]
+pub struct JournalWriter {
+    path: PathBuf,
+    mode: RunMode,
+    file: File,
+}
+
+impl JournalWriter {
+    pub fn create(dir: &Path, mode: RunMode) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let path = dir.join(format!("{:024}-{}.{}", now.as_nanos(), process::id(), EXTENSION));
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        writeln!(file, "{}", HEADER)?;
+        writeln!(file, "mode\t{}", mode)?;
+        writeln!(file, "started\t{}", now.as_secs())?;
+        Ok(Self { path, mode, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records a finished operation; `destination` must already be written.
+    pub fn record(&mut self, source: &Path, destination: &Path) -> io::Result<()> {
+        let entry = JournalEntry {
+            source: source.to_path_buf(),
+            destination: destination.to_path_buf(),
+            fingerprint: Fingerprint::of(destination, self.mode)?,
+        };
+        self.file.write_all(format_entry(&entry).as_bytes())?;
+        self.file.flush()
+    }
+
+    /// Removes the journal of a run that left no trace.
+    pub fn discard(self) {
+        drop(self.file);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Journal {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.to_string_lossy(), e))?;
+        let mut lines = BufReader::new(file).lines();
+        let bad = |what: &str| format!("{}: {}", path.to_string_lossy(), what);
+
+        match lines.next() {
+            Some(Ok(line)) if line == HEADER => {}
+            _ => return Err(bad("not a renamer journal")),
+        }
+
+        let mut mode = None;
+        let mut started = 0;
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line.map_err(|e| bad(&e.to_string()))?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["mode", value] => mode = Some(value.parse::<RunMode>()?),
+                ["started", value] => started = value.parse().map_err(|_| bad("invalid start time"))?,
+                ["op", size, modified, content, source, destination] => entries.push(JournalEntry {
+                    source: decode_path(source)?,
+                    destination: decode_path(destination)?,
+                    fingerprint: Fingerprint {
+                        size: size.parse().map_err(|_| bad("invalid size"))?,
+                        modified_nanos: modified.parse().map_err(|_| bad("invalid time"))?,
+                        content: u64::from_str_radix(content, 16).map_err(|_| bad("invalid content hash"))?,
+                    },
+                }),
+                [""] => {}
+                _ => return Err(bad("unreadable line")),
+            }
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: mode.ok_or_else(|| bad("missing mode"))?,
+            started,
+            entries,
+        })
+    }
+
+    /// The most recent journal in `dir`, if there is one.
+    pub fn latest(dir: &Path) -> Result<Option<Self>, String> {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return Ok(None);
+        };
+        let newest = read_dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == EXTENSION))
+            .max();
+        newest.map(|p| Self::load(&p)).transpose()
+    }
+
+    fn unchanged(&self, entry: &JournalEntry) -> Result<(), String> {
+        match Fingerprint::of(&entry.destination, self.mode) {
+            Ok(current) if current == entry.fingerprint => Ok(()),
+            Ok(_) => Err(format!("{} changed since the run", entry.destination.to_string_lossy())),
+            Err(e) => Err(format!("{}: {}", entry.destination.to_string_lossy(), e)),
+        }
+    }
+
+    fn revert(&self, entry: &JournalEntry) -> Result<(), String> {
+        let failed = |e: io::Error| format!("{}: {}", entry.destination.to_string_lossy(), e);
+        match self.mode {
+            RunMode::Transfer(TransferMode::Move) => {
+                if fs::symlink_metadata(&entry.source).is_ok() {
+                    return Err(format!("{} exists again", entry.source.to_string_lossy()));
+                }
+                if let Some(parent) = entry.source.parent() {
+                    fs::create_dir_all(parent).map_err(failed)?;
+                }
+                transfer(TransferMode::Move, &entry.destination, &entry.source).map_err(failed)
+            }
+            _ => fs::remove_file(&entry.destination).map_err(failed),
+        }
+    }
+
+    // In-place runs are reversed with the same two-phase rename as the run itself.
+    fn revert_in_place(&self, entries: &[&JournalEntry]) -> Result<(), String> {
+        let reversed: Vec<PlannedRename> = entries.iter()
+            .map(|e| PlannedRename { source: e.destination.clone(), destination: e.source.clone(), notes: Vec::new(), tag_update: None })
+            .collect();
+        let staged = in_place::stage(&reversed)?;
+        for (i, entry) in staged.iter().enumerate() {
+            if let Err(e) = in_place::finish(entry) {
+                let left = in_place::restore(&staged, i);
+                return Err(format!("{} ({})", e, in_place::restore_note(&left)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reverts every operation whose result is unchanged. Entries that were
+    /// reverted are dropped from the journal, which is deleted once empty.
+    pub fn undo(self) -> Result<UndoReport, String> {
+        let mut report = UndoReport::default();
+        let mut remaining = Vec::new();
+        let mut reverting = Vec::new();
+
+        for entry in &self.entries {
+            match self.unchanged(entry) {
+                Ok(()) => reverting.push(entry),
+                Err(reason) => {
+                    report.skipped.push(reason);
+                    remaining.push(entry.clone());
+                }
+            }
+        }
+
+        if self.mode == RunMode::InPlace {
+            match self.revert_in_place(&reverting) {
+                Ok(()) => report.reverted = reverting.len(),
+                Err(reason) => {
+                    report.skipped.push(reason);
+                    remaining.extend(reverting.into_iter().cloned());
+                }
+            }
+        } else {
+            for entry in reverting.into_iter().rev() {
+                match self.revert(entry) {
+                    Ok(()) => report.reverted += 1,
+                    Err(reason) => {
+                        report.skipped.push(reason);
+                        remaining.push(entry.clone());
+                    }
+                }
+            }
+        }
+
+        self.rewrite(&remaining)?;
+        Ok(report)
+    }
+
+    fn rewrite(&self, remaining: &[JournalEntry]) -> Result<(), String> {
+        let failed = |e: io::Error| format!("{}: {}", self.path.to_string_lossy(), e);
+        if remaining.is_empty() {
+            return fs::remove_file(&self.path).map_err(failed);
+        }
+        let mut contents = format!("{}\nmode\t{}\nstarted\t{}\n", HEADER, self.mode, self.started);
+        for entry in remaining {
+            contents.push_str(&format_entry(entry));
+        }
+        fs::write(&self.path, contents).map_err(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_encode_path_round_trips_special_characters() {
+        let path = PathBuf::from("/music/50%\tmix\n/трек.mp3");
+        let encoded = encode_path(&path);
+        assert!(!encoded.contains('\t') && !encoded.contains('\n'));
+        assert!(encoded.contains("трек"));
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+    }
+
+    #[test]
+    fn test_undo_copy_skips_modified_files() {
+        let dir = tempdir().unwrap();
+        let journals = dir.path().join("journals");
+        let source = dir.path().join("a.mp3");
+        let kept = dir.path().join("001.mp3");
+        let edited = dir.path().join("002.mp3");
+        fs::write(&source, "a").unwrap();
+        fs::copy(&source, &kept).unwrap();
+        fs::copy(&source, &edited).unwrap();
+
+        let mut writer = JournalWriter::create(&journals, RunMode::Transfer(TransferMode::Copy)).unwrap();
+        writer.record(&source, &kept).unwrap();
+        writer.record(&source, &edited).unwrap();
+        fs::write(&edited, "edited by hand").unwrap();
+
+        let journal = Journal::latest(&journals).unwrap().unwrap();
+        assert_eq!(journal.entries.len(), 2);
+        let report = journal.undo().unwrap();
+
+        assert_eq!(report.reverted, 1);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(!kept.exists());
+        assert!(edited.exists() && source.exists());
+        assert_eq!(Journal::latest(&journals).unwrap().unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn test_undo_notices_edits_that_keep_size_and_time() {
+        let dir = tempdir().unwrap();
+        let journals = dir.path().join("journals");
+        let source = dir.path().join("a.mp3");
+        let copy = dir.path().join("001.mp3");
+        fs::write(&source, "song").unwrap();
+        fs::copy(&source, &copy).unwrap();
+
+        let mut writer = JournalWriter::create(&journals, RunMode::Transfer(TransferMode::Copy)).unwrap();
+        writer.record(&source, &copy).unwrap();
+        let modified = fs::metadata(&copy).unwrap().modified().unwrap();
+        fs::write(&copy, "tune").unwrap();
+        File::options().write(true).open(&copy).unwrap().set_modified(modified).unwrap();
+
+        let report = Journal::latest(&journals).unwrap().unwrap().undo().unwrap();
+
+        assert_eq!(report.reverted, 0);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "tune");
+    }
+
+    #[test]
+    fn test_undo_in_place_reports_a_failed_revert() {
+        let dir = tempdir().unwrap();
+        let journals = dir.path().join("journals");
+        let first = dir.path().join("001.mp3");
+        let second = dir.path().join("002.mp3");
+        fs::write(&first, "a").unwrap();
+        fs::write(&second, "b").unwrap();
+
+        let mut writer = JournalWriter::create(&journals, RunMode::InPlace).unwrap();
+        writer.record(&dir.path().join("a.mp3"), &first).unwrap();
+        writer.record(&dir.path().join("b.mp3"), &second).unwrap();
+        // A new file took one of the old names, so that file cannot go back.
+        fs::write(dir.path().join("b.mp3"), "new").unwrap();
+
+        let report = Journal::latest(&journals).unwrap().unwrap().undo().unwrap();
+
+        assert_eq!(report.reverted, 0);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(report.skipped[0].ends_with("(all files were restored)"));
+        assert_eq!(fs::read_to_string(&first).unwrap(), "a");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "b");
+        assert_eq!(Journal::latest(&journals).unwrap().unwrap().entries.len(), 2);
+    }
+}
@@ -0,0 +1,34 @@
+//! Renaming engine shared by the graphical and command-line front ends.
+//!
+//! Build a [`RenameJob`], turn it into a [`RenamePlan`] to see what would
+//! happen, check it for conflicts with [`RenamePlan::check`], then execute
+//! the plan and follow its [`Progress`] events.
+
+pub mod conflicts;
+pub mod control;
+pub mod dates;
+pub mod device;
+pub mod file_ops;
+pub mod filter;
+pub mod job;
+pub mod journal;
+pub mod sanitize;
+pub mod tags;
+pub mod template;
+pub mod transfer;
+pub mod translit;
+mod in_place;
+
+pub use conflicts::{Conflict, PlanReport};
+pub use file_ops::{Counter, InputMerge, Numbering, SortKey, SortOrder};
+pub use filter::{FileFilter, MANIFEST_NAME};
+pub use control::RunControl;
+pub use dates::{CaptureDate, ClockOffset, ClockOffsets};
+pub use device::DeviceProfile;
+pub use job::{PlannedRename, Progress, RenameJob, RenamePlan};
+pub use journal::{Journal, UndoReport};
+pub use sanitize::{FsProfile, Normalization};
+pub use tags::{AudioTags, TagField, TagWriting};
+pub use template::Template;
+pub use transfer::TransferMode;
+pub use translit::Transliteration;
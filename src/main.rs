@@ -1,4 +1,4 @@
-mod file_ops;
+mod cli;
 mod tasks;
 mod ui;
 use iced::{Settings, Font};
@@ -8,6 +8,11 @@ const MONO_FONT_BYTES: &[u8] = include_bytes!("../assets/NotoSans-Regular.ttf");
 const MONO_FONT_NAME: &str = "Noto Sans";
 
 fn main() -> iced::Result {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     let settings = Settings {
         fonts: vec![Cow::Borrowed(MONO_FONT_BYTES)],
         default_font: Font::with_name(MONO_FONT_NAME),
@@ -0,0 +1,290 @@
+//! Making new names acceptable to the file system of the output target.
+//!
+//! FAT32, exFAT and NTFS reject `" * / : < > ? \ |` and control characters,
+//! names such as `CON` or `LPT1`, and names ending in a dot or a space; ext4
+//! only rejects `/`. Each also limits the length of a name and of a path.
+//! The path limit is counted from the output folder, since where the target
+//! is mounted is not known.
+//!
+//! Names can also be brought into one Unicode form, so that a name typed on
+//! macOS (decomposed, NFD) and the same name typed elsewhere (composed, NFC)
+//! end up identical.
+
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+use crate::file_ops::bytes_to_os_string;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsProfile {
+    /// Memory cards and most players; paths are kept within Windows' 260 characters.
+    #[default]
+    Fat32,
+    ExFat,
+    Ntfs,
+    Ext4,
+}
+
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A name after sanitization, with what had to change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sanitized {
+    pub name: OsString,
+    pub changes: Vec<&'static str>,
+}
+
+impl FsProfile {
+    pub const ALL: [FsProfile; 4] = [FsProfile::Fat32, FsProfile::ExFat, FsProfile::Ntfs, FsProfile::Ext4];
+
+    fn is_windows(self) -> bool {
+        self != FsProfile::Ext4
+    }
+
+    /// Longest name, in UTF-16 units on the Windows file systems and bytes on ext4.
+    pub fn name_limit(self) -> usize {
+        255
+    }
+
+    /// Longest path inside the output folder, in the same units as [`FsProfile::name_limit`].
+    pub fn path_limit(self) -> usize {
+        match self {
+            FsProfile::Fat32 => 260,
+            FsProfile::ExFat => 32_760,
+            FsProfile::Ntfs => 32_767,
+            FsProfile::Ext4 => 4096,
+        }
+    }
+
+    fn units(self, text: &str) -> usize {
+        if self.is_windows() { text.encode_utf16().count() } else { text.len() }
+    }
+
+    fn rejects(self, c: char) -> bool {
+        match self {
+            FsProfile::Ext4 => c == '/' || c == '\0',
+            _ => c.is_ascii_control() || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|'),
+        }
+    }
+
+    /// Makes one file or folder name acceptable, shortening it to `limit` units.
+    pub fn sanitize_name(self, name: &OsStr, limit: usize) -> Sanitized {
+        let mut changes = Vec::new();
+        let text = match name.to_str() {
+            Some(text) => text.to_string(),
+            None if self.is_windows() => {
+                changes.push("characters that are not UTF-8 replaced");
+                name.to_string_lossy().into_owned()
+            }
+            None => return self.sanitize_bytes(name, limit),
+        };
+
+        let mut text: String = text.chars().map(|c| if self.rejects(c) { '_' } else { c }).collect();
+        if text != name.to_string_lossy() && changes.is_empty() {
+            changes.push("characters the file system rejects replaced");
+        }
+        if self.is_windows() {
+            let trimmed = text.trim_end_matches(['.', ' ']);
+            if trimmed.len() < text.len() {
+                text = if trimmed.is_empty() { "_".to_string() } else { trimmed.to_string() };
+                changes.push("trailing dots or spaces removed");
+            }
+            let base = text.split('.').next().unwrap_or_default();
+            if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base.trim_end())) {
+                text.insert(base.len(), '_');
+                changes.push("reserved name changed");
+            }
+        }
+        if self.units(&text) > limit {
+            text = self.shorten(&text, limit);
+            changes.push("shortened to fit the length limit");
+        }
+        Sanitized { name: text.into(), changes }
+    }
+
+    // Keeps the extension and cuts the stem; a dot or space left at the end is dropped too.
+    fn shorten(self, text: &str, limit: usize) -> String {
+        let (stem, ext) = match text.rfind('.') {
+            Some(dot) if dot > 0 && self.units(&text[dot..]) < limit => text.split_at(dot),
+            _ => (text, ""),
+        };
+        let room = limit - self.units(ext);
+        let mut used = 0;
+        let cut = stem.char_indices()
+            .find(|(_, c)| {
+                used += if self.is_windows() { c.len_utf16() } else { c.len_utf8() };
+                used > room
+            })
+            .map_or(stem.len(), |(i, _)| i);
+        let mut stem = &stem[..cut];
+        if self.is_windows() {
+            stem = stem.trim_end_matches(['.', ' ']);
+        }
+        let stem = if stem.is_empty() { "_" } else { stem };
+        format!("{}{}", stem, ext)
+    }
+
+    // ext4 names that are not UTF-8 are kept byte for byte, apart from `/` and length.
+    fn sanitize_bytes(self, name: &OsStr, limit: usize) -> Sanitized {
+        let mut changes = Vec::new();
+        let mut bytes = name.as_encoded_bytes().to_vec();
+        if bytes.contains(&b'/') || bytes.contains(&0) {
+            bytes.iter_mut().filter(|b| **b == b'/' || **b == 0).for_each(|b| *b = b'_');
+            changes.push("characters the file system rejects replaced");
+        }
+        if bytes.len() > limit {
+            let ext = match bytes.iter().rposition(|&b| b == b'.') {
+                Some(dot) if dot > 0 && bytes.len() - dot < limit => bytes.split_off(dot),
+                _ => Vec::new(),
+            };
+            bytes.truncate(limit - ext.len());
+            bytes.extend(ext);
+            changes.push("shortened to fit the length limit");
+        }
+        Sanitized { name: bytes_to_os_string(bytes), changes }
+    }
+
+    /// Sanitizes every component of `folder`, a path relative to the output folder.
+    pub fn sanitize_folder(self, folder: &Path) -> (PathBuf, bool) {
+        let mut changed = false;
+        let mut sanitized = PathBuf::new();
+        for component in folder.components() {
+            match component {
+                Component::Normal(name) => {
+                    let result = self.sanitize_name(name, self.name_limit());
+                    changed |= !result.changes.is_empty();
+                    sanitized.push(result.name);
+                }
+                other => sanitized.push(other),
+            }
+        }
+        (sanitized, changed)
+    }
+
+    /// How many units `path`, relative to the output folder, takes up.
+    pub fn path_units(self, path: &Path) -> usize {
+        match path.to_str() {
+            Some(text) => self.units(text),
+            None => path.as_os_str().as_encoded_bytes().len(),
+        }
+    }
+}
+
+/// Unicode form of the new names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalization {
+    /// Names keep whatever form the template and the original names produce.
+    #[default]
+    Unchanged,
+    /// Composed form, as Windows and Linux usually store names.
+    Nfc,
+    /// Decomposed form, as older macOS file systems store names.
+    Nfd,
+}
+
+impl Normalization {
+    pub const ALL: [Normalization; 3] = [Normalization::Unchanged, Normalization::Nfc, Normalization::Nfd];
+
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Normalization::Unchanged => text.to_string(),
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfd => text.nfd().collect(),
+        }
+    }
+}
+
+/// `name` with case and Unicode form folded away: two names with the same key
+/// are one and the same on FAT, NTFS and macOS file systems.
+pub fn collision_key(name: &str) -> String {
+    name.nfd().flat_map(char::to_lowercase).nfc().collect()
+}
+
+impl fmt::Display for FsProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FsProfile::Fat32 => "fat32",
+            FsProfile::ExFat => "exfat",
+            FsProfile::Ntfs => "ntfs",
+            FsProfile::Ext4 => "ext4",
+        })
+    }
+}
+
+impl FromStr for FsProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FsProfile::ALL
+            .into_iter()
+            .find(|profile| profile.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("Unknown file system: {} (expected fat32, exfat, ntfs or ext4)", s))
+    }
+}
+
+impl fmt::Display for Normalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Normalization::Unchanged => "none",
+            Normalization::Nfc => "nfc",
+            Normalization::Nfd => "nfd",
+        })
+    }
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Normalization::ALL
+            .into_iter()
+            .find(|form| form.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("Unknown Unicode form: {} (expected none, nfc or nfd)", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(profile: FsProfile, name: &str) -> (String, Vec<&'static str>) {
+        let result = profile.sanitize_name(OsStr::new(name), profile.name_limit());
+        (result.name.into_string().unwrap(), result.changes)
+    }
+
+    #[test]
+    fn test_windows_profiles_fix_characters_reserved_names_and_endings() {
+        assert_eq!(sanitize(FsProfile::Fat32, "01_What? Live: 1999.mp3").0, "01_What_ Live_ 1999.mp3");
+        assert_eq!(sanitize(FsProfile::Ntfs, "con.mp3"), ("con_.mp3".to_string(), vec!["reserved name changed"]));
+        assert_eq!(sanitize(FsProfile::ExFat, "Vol. 2. ").0, "Vol. 2");
+        assert_eq!(sanitize(FsProfile::Ext4, "What? <Live>.mp3"), ("What? <Live>.mp3".to_string(), vec![]));
+        assert_eq!(sanitize(FsProfile::Ext4, "AC/DC.mp3").0, "AC_DC.mp3");
+    }
+
+    #[test]
+    fn test_long_names_keep_their_extension() {
+        let long = format!("{}.flac", "я".repeat(300));
+        let (fat, _) = sanitize(FsProfile::Fat32, &long);
+        assert_eq!(fat.encode_utf16().count(), 255);
+        assert!(fat.ends_with("я.flac"));
+        let (ext4, changes) = sanitize(FsProfile::Ext4, &long);
+        assert_eq!((ext4.len(), changes), (255, vec!["shortened to fit the length limit"]));
+    }
+
+    #[test]
+    fn test_normalization_and_collision_key() {
+        let composed = "Beyonc\u{e9}.mp3";
+        let decomposed = "Beyonce\u{301}.mp3";
+        assert_eq!(Normalization::Nfc.apply(decomposed), composed);
+        assert_eq!(Normalization::Nfd.apply(composed), decomposed);
+        assert_eq!(Normalization::Unchanged.apply(decomposed), decomposed);
+        assert_eq!(collision_key(decomposed), collision_key("BEYONC\u{c9}.MP3"));
+        assert_ne!(collision_key(composed), collision_key("Beyonce.mp3"));
+    }
+}
@@ -0,0 +1,288 @@
+//! Audio tags (ID3v2, Vorbis comments, MP4 atoms) used for ordering and naming.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use lofty::config::{ParseOptions, WriteOptions};
+use lofty::prelude::{Accessor, TagExt, TaggedFileExt};
+use lofty::probe::Probe;
+use lofty::tag::Tag;
+
+/// The tag fields the renamer understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagField {
+    Artist,
+    Album,
+    Disc,
+    Track,
+    Title,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 5] = [TagField::Artist, TagField::Album, TagField::Disc, TagField::Track, TagField::Title];
+}
+
+impl fmt::Display for TagField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TagField::Artist => "artist",
+            TagField::Album => "album",
+            TagField::Disc => "disc",
+            TagField::Track => "track",
+            TagField::Title => "title",
+        })
+    }
+}
+
+impl FromStr for TagField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TagField::ALL
+            .into_iter()
+            .find(|field| field.to_string() == s)
+            .ok_or_else(|| format!("Unknown tag field: {} (expected artist, album, disc, track or title)", s))
+    }
+}
+
+/// Tags read from one audio file. Fields missing from the file are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub disc: Option<u32>,
+    pub track: Option<u32>,
+}
+
+impl AudioTags {
+    /// Reads the primary tag of an MP3, FLAC, Ogg or M4A file, falling back to
+    /// any other tag it carries. Returns `None` for files that cannot be parsed
+    /// or have no tag at all.
+    pub fn read(path: &Path) -> Option<Self> {
+        Self::probe(path).ok().flatten()
+    }
+
+    /// Like [`AudioTags::read`], telling files that cannot be parsed, and so
+    /// cannot be given tags either, apart from files without a tag.
+    pub fn probe(path: &Path) -> Result<Option<Self>, String> {
+        let file = Probe::open(path).map_err(|e| e.to_string())?
+            .options(ParseOptions::new().read_properties(false).read_cover_art(false))
+            .read()
+            .map_err(|e| e.to_string())?;
+        let Some(tag) = file.primary_tag().or_else(|| file.first_tag()) else {
+            return Ok(None);
+        };
+        let text = |value: Option<std::borrow::Cow<'_, str>>| {
+            value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+        };
+        Ok(Some(Self {
+            artist: text(tag.artist()),
+            album: text(tag.album()),
+            title: text(tag.title()),
+            disc: tag.disk(),
+            track: tag.track(),
+        }))
+    }
+
+    /// Text value of `field`, numbers unpadded.
+    pub fn get(&self, field: TagField) -> Option<String> {
+        match field {
+            TagField::Artist => self.artist.clone(),
+            TagField::Album => self.album.clone(),
+            TagField::Title => self.title.clone(),
+            TagField::Disc => self.disc.map(|n| n.to_string()),
+            TagField::Track => self.track.map(|n| n.to_string()),
+        }
+    }
+
+    pub fn has(&self, field: TagField) -> bool {
+        match field {
+            TagField::Artist => self.artist.is_some(),
+            TagField::Album => self.album.is_some(),
+            TagField::Title => self.title.is_some(),
+            TagField::Disc => self.disc.is_some(),
+            TagField::Track => self.track.is_some(),
+        }
+    }
+}
+
+/// Describes which of `fields` are missing from `tags`, or `None` when all are present.
+pub fn missing_fields(tags: Option<&AudioTags>, fields: &[TagField]) -> Option<String> {
+    if fields.is_empty() {
+        return None;
+    }
+    let Some(tags) = tags else {
+        return Some("no audio tags".to_string());
+    };
+    let missing: Vec<String> = fields.iter()
+        .filter(|field| !tags.has(**field))
+        .map(|field| field.to_string())
+        .collect();
+    (!missing.is_empty()).then(|| format!("missing tags: {}", missing.join(", ")))
+}
+
+/// Which tags are written into each output file besides the track number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TagWriting {
+    /// Also write the total number of tracks.
+    pub total_tracks: bool,
+    /// Also write the title, normalized with [`normalize_title`].
+    pub title: bool,
+}
+
+/// Tag values to write into one output file, decided when the plan is made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagUpdate {
+    pub track: u32,
+    pub total_tracks: Option<u32>,
+    pub title: Option<String>,
+}
+
+impl TagUpdate {
+    /// Builds the update for `source`, numbered `track` out of `total`.
+    /// The title comes from `tags`, the source's current tags, or from the
+    /// file name when they have none.
+    pub fn new(writing: TagWriting, source: &Path, tags: Option<&AudioTags>, track: u64, total: usize) -> Self {
+        let title = writing.title.then(|| {
+            let raw = tags
+                .and_then(|tags| tags.title.clone())
+                .unwrap_or_else(|| source.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+            normalize_title(&raw)
+        });
+        Self {
+            track: u32::try_from(track).unwrap_or(u32::MAX),
+            total_tracks: writing.total_tracks.then(|| u32::try_from(total).unwrap_or(u32::MAX)),
+            title,
+        }
+    }
+
+    /// Rewrites the tag of the file at `path` in place; the audio data is not touched.
+    /// Files without a tag get a new one of their format's usual kind.
+    pub fn write_to(&self, path: &Path) -> Result<(), String> {
+        let mut file = Probe::open(path).map_err(|e| e.to_string())?
+            .options(ParseOptions::new().read_properties(false))
+            .read()
+            .map_err(|e| e.to_string())?;
+        let tag = match file.primary_tag_mut() {
+            Some(tag) => tag,
+            None => {
+                file.insert_tag(Tag::new(file.primary_tag_type()));
+                file.primary_tag_mut().ok_or("the file format cannot hold tags")?
+            }
+        };
+        tag.set_track(self.track);
+        if let Some(total) = self.total_tracks {
+            tag.set_track_total(total);
+        }
+        if let Some(title) = &self.title {
+            tag.set_title(title.clone());
+        }
+        tag.save_to_path(path, WriteOptions::default()).map_err(|e| e.to_string())
+    }
+}
+
+/// Cleans up a title taken from a tag or a file name: a leading track number
+/// followed by a separator, as in `01 - `, `02. ` or `03_`, is dropped,
+/// underscores become spaces and runs of whitespace collapse.
+pub fn normalize_title(raw: &str) -> String {
+    let after_number = raw.trim_start().trim_start_matches(|c: char| c.is_ascii_digit());
+    let separated = after_number.trim_start();
+    let title = match separated.chars().next() {
+        Some('-' | '.' | ')' | '_') if after_number.len() < raw.trim_start().len() => {
+            &separated[1..]
+        }
+        _ => raw,
+    };
+    let title = title.replace('_', " ");
+    let words = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    if words.is_empty() { raw.trim().to_string() } else { words }
+}
+
+/// Writes a minimal FLAC file carrying `comments` as Vorbis comments.
+#[cfg(test)]
+pub(crate) fn write_test_flac(path: &Path, comments: &[(&str, &str)]) {
+    let mut bytes = b"fLaC".to_vec();
+    // STREAMINFO: 4096-sample blocks, 44.1 kHz, 2 channels, 16 bits, no samples.
+    bytes.extend([0x00, 0x00, 0x00, 34]);
+    bytes.extend([0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0]);
+    bytes.extend([0x0A, 0xC4, 0x42, 0xF0, 0, 0, 0, 0]);
+    bytes.extend([0; 16]);
+
+    let mut block = Vec::new();
+    let vendor = b"renamer";
+    block.extend((vendor.len() as u32).to_le_bytes());
+    block.extend(vendor);
+    block.extend((comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        let comment = format!("{}={}", key, value);
+        block.extend((comment.len() as u32).to_le_bytes());
+        block.extend(comment.as_bytes());
+    }
+    // VORBIS_COMMENT, flagged as the last metadata block.
+    bytes.push(0x84);
+    bytes.extend(&(block.len() as u32).to_be_bytes()[1..]);
+    bytes.extend(block);
+    std::fs::write(path, bytes).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_vorbis_comments() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        write_test_flac(&path, &[
+            ("ARTIST", "Nina Simone"),
+            ("ALBUM", "Pastel Blues"),
+            ("TITLE", " Sinnerman "),
+            ("DISCNUMBER", "1"),
+            ("TRACKNUMBER", "9"),
+        ]);
+
+        let tags = AudioTags::read(&path).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("Nina Simone"));
+        assert_eq!(tags.album.as_deref(), Some("Pastel Blues"));
+        assert_eq!(tags.title.as_deref(), Some("Sinnerman"));
+        assert_eq!((tags.disc, tags.track), (Some(1), Some(9)));
+    }
+
+    #[test]
+    fn test_missing_fields_are_described() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("song.flac");
+        write_test_flac(&path, &[("TITLE", "Intro")]);
+        let tags = AudioTags::read(&path);
+
+        assert_eq!(missing_fields(tags.as_ref(), &[TagField::Title]), None);
+        assert_eq!(
+            missing_fields(tags.as_ref(), &[TagField::Track, TagField::Title, TagField::Artist]),
+            Some("missing tags: track, artist".to_string())
+        );
+        assert_eq!(missing_fields(None, &[TagField::Track]), Some("no audio tags".to_string()));
+        assert_eq!(AudioTags::read(&dir.path().join("absent.mp3")), None);
+        std::fs::write(dir.path().join("broken.mp3"), "not audio").unwrap();
+        assert!(AudioTags::probe(&dir.path().join("broken.mp3")).is_err());
+    }
+
+    #[test]
+    fn test_tag_update_writes_track_total_and_title() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("03_the_long_way.flac");
+        write_test_flac(&path, &[("ARTIST", "Someone"), ("TRACKNUMBER", "7")]);
+
+        let update = TagUpdate::new(TagWriting { total_tracks: true, title: true }, &path, AudioTags::read(&path).as_ref(), 3, 12);
+        assert_eq!(update.title.as_deref(), Some("the long way"));
+        update.write_to(&path).unwrap();
+
+        let tags = AudioTags::read(&path).unwrap();
+        assert_eq!(tags.track, Some(3));
+        assert_eq!(tags.title.as_deref(), Some("the long way"));
+        assert_eq!(tags.artist.as_deref(), Some("Someone"));
+        assert_eq!(normalize_title("99 Luftballons"), "99 Luftballons");
+        assert_eq!(normalize_title("01 - Intro"), "Intro");
+    }
+}
@@ -1,81 +1,80 @@
-use std::path::{Path};
 use std::path::PathBuf;
 use rfd::FileDialog;
-use async_stream::stream;
-use std::fs;
+use futures::{Stream, StreamExt};
+use std::thread;
+use std::time::Duration;
+use futures::channel::mpsc;
+use renamer::file_ops::display_path;
+use renamer::{Journal, Progress, RenameJob, RenamePlan, RunControl, UndoReport};
 use crate::ui::Message;
-use crate::file_ops::list_files_in_directory;
-use crate::file_ops::rename_files_with_leading_zeros;
 
-pub async fn folder_selection(default_dir: PathBuf) -> String {
+const SCAN_PROGRESS_EVERY: usize = 100;
+
+pub async fn folder_selection(default_dir: PathBuf) -> Option<PathBuf> {
     FileDialog::new()
         .set_directory(default_dir)
         .pick_folder()
-        .map_or("".to_string(), |p| p.to_string_lossy().to_string())
 }
 
-// Streamed renaming with progress
-pub fn perform_renaming_with_progress(
-    input: Option<String>,
-    output: Option<String>,
-    ext: String,
-    padding_zeros: usize,
-    include_original_name: bool,
-) -> impl futures::Stream<Item = Message> {
-    let input_path = input.unwrap_or_default();
-    let output_path = output.unwrap_or_default();
-    let ext_clean = ext.trim_start_matches('.').to_string();
-
-    stream! {
-        let files = match list_files_in_directory(&input_path, &ext_clean) {
-            Ok(f) => f,
-            Err(e) => {
-                yield Message::RenamingDone(Err(e));
-                return;
-            }
-        };
-
-        let total_files = files.len();
-        if total_files == 0 {
-            yield Message::RenamingDone(Err("No files found to rename.".to_string()));
-            return;
-        }
+// Streamed renaming with progress, for a plan the user already reviewed and confirmed
+pub fn execute_plan_with_progress(plan: RenamePlan, control: RunControl) -> impl Stream<Item = Message> {
+    plan.execute_with_control(control).map(progress_message)
+}
 
-        let output_dir = Path::new(&output_path);
-        if let Err(e) = fs::create_dir_all(output_dir) {
-            yield Message::RenamingDone(Err(e.to_string()));
+// Background preview scan. Waits `debounce` first so that typing does not
+// start a scan per keystroke; once the task is aborted the receiver is gone
+// and the scanning thread gives up at its next check.
+pub fn scan_preview(job: RenameJob, generation: u64, debounce: Duration) -> impl Stream<Item = Message> {
+    let (sender, receiver) = mpsc::unbounded();
+    thread::spawn(move || {
+        thread::sleep(debounce);
+        if sender.is_closed() {
             return;
         }
-
-        let new_names = rename_files_with_leading_zeros(&files, padding_zeros, include_original_name);
-        let mut result_names = Vec::new();
-
-        for (i, (old_path, new_name)) in files.iter().zip(new_names.iter()).enumerate() {
-            let new_path = output_dir.join(new_name);
-            if let Err(e) = fs::copy(old_path, &new_path) {
-                yield Message::RenamingDone(Err(e.to_string()));
-                return;
+        let mut reported = 0;
+        let result = job.plan_with_progress(|found| {
+            // The final count is repeated while tags and dates are read; it is reported once.
+            if found % SCAN_PROGRESS_EVERY == 0 && found != reported {
+                reported = found;
+                let _ = sender.unbounded_send(Message::ScanProgress(generation, found));
             }
+            !sender.is_closed()
+        });
+        let _ = sender.unbounded_send(Message::ScanFinished(generation, result));
+    });
+    receiver
+}
 
-            result_names.push(new_path.to_string_lossy().to_string());
-
-            yield Message::RenamingProgress(i + 1, total_files);
-        }
+pub async fn undo_last_run(journal_dir: Option<PathBuf>) -> Result<UndoReport, String> {
+    let dir = journal_dir.ok_or("No folder for undo journals is available.")?;
+    match Journal::latest(&dir)? {
+        Some(journal) => journal.undo(),
+        None => Err("There is no run to undo.".to_string()),
+    }
+}
 
-        yield Message::RenamingDone(Ok(result_names));
+fn progress_message(event: Progress) -> Message {
+    match event {
+        Progress::Renamed { done, total, .. } => Message::RenamingProgress(done, total),
+        Progress::Finished(result) => Message::RenamingDone(result.map(|paths| {
+            paths.iter().map(|p| display_path(p)).collect()
+        })),
+        Progress::Cancelled { completed } => Message::RenamingCancelled(
+            completed.iter().map(|p| display_path(p)).collect()
+        ),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::Path;
     use std::fs::{File};
     use std::io::Write;
     use tempfile::tempdir;
-    use futures::StreamExt;
 
     #[tokio::test]
-    async fn test_perform_renaming_with_progress_success() {
+    async fn test_execute_plan_with_progress_success() {
         let input_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
 
@@ -87,13 +86,13 @@ mod tests {
         }
 
         // Run the stream
-        let mut stream = Box::pin(perform_renaming_with_progress(
-            Some(input_dir.path().to_string_lossy().to_string()),
-            Some(output_dir.path().to_string_lossy().to_string()),
-            "mp3".into(),
-            3,
-            true,
-        ));
+        let plan = RenameJob::new(input_dir.path(), output_dir.path())
+            .extension("mp3")
+            .padding_zeros(3)
+            .include_original_name(true)
+            .plan()
+            .unwrap();
+        let mut stream = Box::pin(execute_plan_with_progress(plan, RunControl::default()));
 
         let mut progress_updates = Vec::new();
         let mut final_result = None;
@@ -122,19 +121,19 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_perform_renaming_with_progress_empty_input() {
+    async fn test_execute_plan_with_progress_empty_input() {
         let input_dir = tempdir().unwrap();
         let output_dir = tempdir().unwrap();
 
         // No files created in input_dir
 
-        let mut stream = Box::pin(perform_renaming_with_progress(
-            Some(input_dir.path().to_string_lossy().to_string()),
-            Some(output_dir.path().to_string_lossy().to_string()),
-            "mp3".into(),
-            3,
-            true,
-        ));
+        let plan = RenameJob::new(input_dir.path(), output_dir.path())
+            .extension("mp3")
+            .padding_zeros(3)
+            .include_original_name(true)
+            .plan()
+            .unwrap();
+        let mut stream = Box::pin(execute_plan_with_progress(plan, RunControl::default()));
 
         let mut final_result = None;
 
@@ -147,4 +146,18 @@ mod tests {
         let Err(err) = final_result.unwrap() else { panic!("Expected error for empty input") };
         assert_eq!(err, "No files found to rename.");
     }
+
+    #[tokio::test]
+    async fn test_scan_preview_tags_result_with_generation() {
+        let input_dir = tempdir().unwrap();
+        for i in 1..=2 {
+            File::create(input_dir.path().join(format!("track{}.mp3", i))).unwrap();
+        }
+
+        let job = RenameJob::new(input_dir.path(), input_dir.path().join("output"));
+        let messages: Vec<Message> = scan_preview(job, 7, Duration::ZERO).collect().await;
+
+        let Some(Message::ScanFinished(7, Ok(plan))) = messages.last() else { panic!("Expected a finished scan") };
+        assert_eq!(plan.len(), 2);
+    }
 }
@@ -0,0 +1,436 @@
+//! Naming templates such as `{n:03}-{stem}{ext}` or `{parent} - {n:02} {stem|lower}{ext}`.
+//!
+//! A template is literal text with tokens in braces. A token may carry a
+//! format after a colon and any number of filters after `|`:
+//!
+//! | Token          | Value                                                  |
+//! |----------------|--------------------------------------------------------|
+//! | `{n}`          | Sequence number, padded to the job's padding           |
+//! | `{n:03}`       | Sequence number, zero-padded to 3 digits               |
+//! |                | (hierarchical numbering prefixes the folder, `1-001`)  |
+//! | `{total}`      | Number of files in the run (`{total:02}` pads it)      |
+//! | `{stem}`       | Original name without extension                        |
+//! | `{ext}`        | Original extension with its dot, or nothing            |
+//! | `{name}`       | Original name with extension                           |
+//! | `{parent}`     | Name of the folder containing the file                 |
+//! | `{size}`       | File size in bytes                                     |
+//! | `{mtime:%Y%m%d}` | Modification time (default format `%Y-%m-%d`)       |
+//! | `{ctime:%Y%m%d}` | Creation time (default format `%Y-%m-%d`)           |
+//! | `{date:%Y%m%d}` | Capture date of a photo or video, see [`crate::dates`] |
+//! | `{artist}`, `{album}`, `{title}` | Audio tags; empty when missing, `/` and `\` become `_` |
+//! | `{disc}`, `{track:02}` | Disc and track numbers from the audio tags      |
+//!
+//! Filters: `lower`, `upper`, `title`, `trim`, `trunc:N` (keep the first N
+//! characters) and `pad:N` (left-pad with zeros to N characters).
+//! Write `{{` and `}}` for literal braces.
+//!
+//! Names are rendered as `OsString`, so `{stem}`, `{ext}`, `{name}` and
+//! `{parent}` keep the original bytes of names that are not valid UTF-8.
+//! Filters work on text and leave such values unchanged.
+//!
+//! A template always names a file, never a path: `/` and `\` are rejected in
+//! the literal text and in date formats, and replaced by `_` in token values
+//! such as tags.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local};
+use crate::file_ops::bytes_to_os_string;
+use crate::tags::{AudioTags, TagField};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const SEPARATORS: [char; 2] = ['/', '\\'];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Index { width: Option<usize> },
+    Total { width: Option<usize> },
+    Stem,
+    Ext,
+    Name,
+    Parent,
+    Size,
+    Modified(String),
+    Created(String),
+    Date(String),
+    Tag { field: TagField, width: Option<usize> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Lower,
+    Upper,
+    Title,
+    Trim,
+    Truncate(usize),
+    Pad(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Token { token: Token, filters: Vec<Filter> },
+}
+
+/// A parsed, validated naming template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// Everything a template can refer to for one file.
+#[derive(Debug, Clone, Copy)]
+pub struct NameContext<'a> {
+    pub path: &'a Path,
+    pub index: u64,
+    /// Folder number and its width, for hierarchical numbering.
+    pub folder: Option<(u64, usize)>,
+    pub total: u64,
+    pub padding_zeros: usize,
+    /// Needed only when [`Template::needs_metadata`] is true.
+    pub metadata: Option<&'a Metadata>,
+    /// Needed only when [`Template::tag_fields`] is not empty.
+    pub tags: Option<&'a AudioTags>,
+    /// Needed only when [`Template::needs_date`] is true.
+    pub date: Option<DateTime<Local>>,
+}
+
+fn parse_number(value: &str, what: &str, example: &str) -> Result<usize, String> {
+    value.parse::<usize>()
+        .map_err(|_| format!("{} needs a number, e.g. {}", what, example))
+}
+
+fn parse_date_format(format: Option<&str>, token: &str) -> Result<String, String> {
+    let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid date format '{}' in {{{}}}", format, token));
+    }
+    // Specifiers such as `%D` and `%x` also produce slashes, so the formatted text is checked.
+    let sample = DateTime::<Local>::from(SystemTime::UNIX_EPOCH).format(format).to_string();
+    if sample.contains(SEPARATORS) {
+        return Err(format!("Date format '{}' in {{{}}} puts a path separator into the file name", format, token));
+    }
+    Ok(format.to_string())
+}
+
+fn parse_token(name: &str, format: Option<&str>) -> Result<Token, String> {
+    let no_format = |token: Token| match format {
+        Some(format) => Err(format!("{{{}}} does not take a format, found ':{}'", name, format)),
+        None => Ok(token),
+    };
+    match name {
+        "n" | "index" => {
+            let width = format
+                .map(|f| parse_number(f, "The index format", "{n:03}"))
+                .transpose()?;
+            Ok(Token::Index { width })
+        }
+        "total" => {
+            let width = format
+                .map(|f| parse_number(f, "The total format", "{total:02}"))
+                .transpose()?;
+            Ok(Token::Total { width })
+        }
+        "stem" => no_format(Token::Stem),
+        "ext" => no_format(Token::Ext),
+        "name" => no_format(Token::Name),
+        "parent" => no_format(Token::Parent),
+        "size" => no_format(Token::Size),
+        "mtime" => Ok(Token::Modified(parse_date_format(format, name)?)),
+        "ctime" => Ok(Token::Created(parse_date_format(format, name)?)),
+        "date" => Ok(Token::Date(parse_date_format(format, name)?)),
+        "disc" | "track" => {
+            let width = format
+                .map(|f| parse_number(f, "The disc and track format", "{track:02}"))
+                .transpose()?;
+            Ok(Token::Tag { field: name.parse()?, width })
+        }
+        "artist" | "album" | "title" => no_format(Token::Tag { field: name.parse()?, width: None }),
+        "" => Err("Empty token {}".to_string()),
+        other => Err(format!(
+            "Unknown token {{{}}}; expected n, total, stem, ext, name, parent, size, mtime, ctime, date, \
+             artist, album, title, disc or track",
+            other
+        )),
+    }
+}
+
+fn parse_filter(filter: &str) -> Result<Filter, String> {
+    let (name, arg) = match filter.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (filter, None),
+    };
+    let required = |example: &str| {
+        arg.ok_or_else(|| format!("Filter '{}' needs a number, e.g. {}", name, example))
+    };
+    match name {
+        "lower" => Ok(Filter::Lower),
+        "upper" => Ok(Filter::Upper),
+        "title" => Ok(Filter::Title),
+        "trim" => Ok(Filter::Trim),
+        "trunc" => Ok(Filter::Truncate(parse_number(required("{stem|trunc:20}")?, "Filter 'trunc'", "{stem|trunc:20}")?)),
+        "pad" => Ok(Filter::Pad(parse_number(required("{size|pad:8}")?, "Filter 'pad'", "{size|pad:8}")?)),
+        other => Err(format!(
+            "Unknown filter '{}'; expected lower, upper, title, trim, trunc:N or pad:N",
+            other
+        )),
+    }
+}
+
+impl Template {
+    /// Parses and validates a template. Errors name the offending token and
+    /// its character position (counted from 1).
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().enumerate().peekable();
+
+        while let Some((position, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|&(_, next)| next) == Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek().map(|&(_, next)| next) == Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(format!("Unmatched '}}' at position {}; write '}}}}' for a literal brace", position + 1)),
+                '{' => {
+                    let mut body = String::new();
+                    let mut closed = false;
+                    for (_, c) in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        body.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("Unclosed '{{' at position {}", position + 1));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+
+                    let mut pieces = body.split('|');
+                    let head = pieces.next().unwrap_or_default().trim();
+                    let (name, format) = match head.split_once(':') {
+                        Some((name, format)) => (name, Some(format)),
+                        None => (head, None),
+                    };
+                    let at = |e: String| format!("{} (position {})", e, position + 1);
+                    let token = parse_token(name, format).map_err(at)?;
+                    let filters = pieces
+                        .map(|f| parse_filter(f.trim()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(at)?;
+                    parts.push(Part::Token { token, filters });
+                }
+                '/' | '\\' => return Err(format!("Path separator '{}' at position {} is not allowed in a file name", c, position + 1)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if parts.is_empty() {
+            return Err("The template is empty.".to_string());
+        }
+        Ok(Self { parts })
+    }
+
+    /// The fixed naming used before templates existed: `NNN.ext` or `NNN_original.ext`.
+    pub fn legacy(include_original_name: bool) -> Self {
+        let source = if include_original_name { "{n}_{name}" } else { "{n}{ext}" };
+        Self::parse(source).expect("built-in template is valid")
+    }
+
+    /// Whether rendering reads file size or dates, so callers can skip `stat` otherwise.
+    pub fn needs_metadata(&self) -> bool {
+        self.parts.iter().any(|part| matches!(
+            part,
+            Part::Token { token: Token::Size | Token::Modified(_) | Token::Created(_), .. }
+        ))
+    }
+
+    /// Whether rendering needs the capture date, which means reading the file's contents.
+    pub fn needs_date(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Token { token: Token::Date(_), .. }))
+    }
+
+    /// Audio tag fields the template refers to, so callers read tags only when needed.
+    pub fn tag_fields(&self) -> Vec<TagField> {
+        let mut fields = Vec::new();
+        for part in &self.parts {
+            if let Part::Token { token: Token::Tag { field, .. }, .. } = part
+                && !fields.contains(field)
+            {
+                fields.push(*field);
+            }
+        }
+        fields
+    }
+
+    pub fn render(&self, context: &NameContext) -> OsString {
+        let mut rendered = OsString::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push(text),
+                Part::Token { token, filters } => {
+                    let value = token_value(token, context);
+                    match value.into_string() {
+                        Ok(text) => rendered.push(filters.iter().fold(text, apply_filter).replace(SEPARATORS, "_")),
+                        Err(raw) => rendered.push(replace_separator_bytes(raw)),
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+// Values that are not UTF-8 are fixed byte by byte; `/` and `\` are ASCII, so
+// they never occur inside a multi-byte character.
+fn replace_separator_bytes(raw: OsString) -> OsString {
+    let bytes = raw.as_encoded_bytes()
+        .iter()
+        .map(|&b| if b == b'/' || b == b'\\' { b'_' } else { b })
+        .collect();
+    bytes_to_os_string(bytes)
+}
+
+fn format_time(time: Option<SystemTime>, format: &str) -> String {
+    time.map(|t| DateTime::<Local>::from(t).format(format).to_string())
+        .unwrap_or_default()
+}
+
+fn token_value(token: &Token, context: &NameContext) -> OsString {
+    let path = context.path;
+    let text = match token {
+        Token::Index { width } => {
+            let index = format!("{:0width$}", context.index, width = width.unwrap_or(context.padding_zeros));
+            match context.folder {
+                Some((folder, folder_width)) => format!("{:0folder_width$}-{}", folder, index),
+                None => index,
+            }
+        }
+        Token::Total { width } => format!("{:0width$}", context.total, width = width.unwrap_or(0)),
+        Token::Stem => return path.file_stem().unwrap_or_default().to_os_string(),
+        Token::Ext => {
+            let mut ext = OsString::new();
+            if let Some(e) = path.extension() {
+                ext.push(".");
+                ext.push(e);
+            }
+            return ext;
+        }
+        Token::Name => return path.file_name().unwrap_or_default().to_os_string(),
+        Token::Parent => {
+            return path.parent().and_then(Path::file_name).map(OsStr::to_os_string).unwrap_or_default();
+        }
+        Token::Size => context.metadata.map(|m| m.len().to_string()).unwrap_or_default(),
+        Token::Modified(format) => format_time(context.metadata.and_then(|m| m.modified().ok()), format),
+        Token::Created(format) => format_time(context.metadata.and_then(|m| m.created().ok()), format),
+        Token::Date(format) => context.date
+            .map(|date| date.format(format).to_string())
+            .unwrap_or_default(),
+        Token::Tag { field, width } => {
+            let value = context.tags.and_then(|tags| tags.get(*field)).unwrap_or_default();
+            match width {
+                Some(width) if !value.is_empty() => format!("{:0>width$}", value, width = *width),
+                _ => value,
+            }
+        }
+    };
+    text.into()
+}
+
+fn title_case(value: &str) -> String {
+    let mut result = String::new();
+    let mut at_word_start = true;
+    for c in value.chars() {
+        if at_word_start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.extend(c.to_lowercase());
+        }
+        at_word_start = !c.is_alphanumeric();
+    }
+    result
+}
+
+fn apply_filter(value: String, filter: &Filter) -> String {
+    match filter {
+        Filter::Lower => value.to_lowercase(),
+        Filter::Upper => value.to_uppercase(),
+        Filter::Title => title_case(&value),
+        Filter::Trim => value.trim().to_string(),
+        Filter::Truncate(max) => value.chars().take(*max).collect(),
+        Filter::Pad(width) => format!("{:0>width$}", value, width = *width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, path: &str, index: u64) -> OsString {
+        let template = Template::parse(source).unwrap();
+        template.render(&NameContext { path: Path::new(path), index, folder: None, total: 10, padding_zeros: 3, metadata: None, tags: None, date: None })
+    }
+
+    #[test]
+    fn test_render_tokens_and_filters() {
+        assert_eq!(render("{n:03}-{stem}{ext}", "/music/Album/Song.mp3", 7), "007-Song.mp3");
+        assert_eq!(render("{parent} - {n:02} {stem|lower}{ext}", "/music/Album/Song.MP3", 1), "Album - 01 song.MP3");
+        assert_eq!(render("{n}_{name|upper|trunc:6}", "/a/hello world.mp3", 12), "012_HELLO ");
+        assert_eq!(render("{{{stem|title}}}", "/a/the long way.mp3", 1), "{The Long Way}");
+    }
+
+    #[test]
+    fn test_legacy_template_matches_fixed_naming() {
+        let path = Path::new("song1.mp3");
+        let context = NameContext { path, index: 1, folder: None, total: 2, padding_zeros: 2, metadata: None, tags: None, date: None };
+        assert_eq!(Template::legacy(true).render(&context), "01_song1.mp3");
+        assert_eq!(Template::legacy(false).render(&context), "01.mp3");
+    }
+
+    #[test]
+    fn test_render_audio_tags() {
+        let template = Template::parse("{disc}-{track:02} {artist} - {title}{ext}").unwrap();
+        assert_eq!(template.tag_fields(), vec![TagField::Disc, TagField::Track, TagField::Artist, TagField::Title]);
+
+        let tags = AudioTags { artist: Some("Nina Simone".into()), title: Some("Sinnerman".into()), disc: Some(1), track: Some(9), ..AudioTags::default() };
+        let context = |tags| NameContext { path: Path::new("/a/x.flac"), index: 1, folder: None, total: 1, padding_zeros: 2, metadata: None, tags, date: None };
+        assert_eq!(template.render(&context(Some(&tags))), "1-09 Nina Simone - Sinnerman.flac");
+        assert_eq!(template.render(&context(None)), "-  - .flac");
+    }
+
+    #[test]
+    fn test_parse_reports_clear_errors() {
+        assert_eq!(
+            Template::parse("{n}-{genre}").unwrap_err(),
+            "Unknown token {genre}; expected n, total, stem, ext, name, parent, size, mtime, ctime, date, \
+             artist, album, title, disc or track (position 5)"
+        );
+        assert_eq!(Template::parse("{n:03").unwrap_err(), "Unclosed '{' at position 1");
+        assert_eq!(
+            Template::parse("{stem|trunc}").unwrap_err(),
+            "Filter 'trunc' needs a number, e.g. {stem|trunc:20} (position 1)"
+        );
+        assert!(Template::parse("{mtime:%Q}").is_err());
+        assert!(Template::parse("{n}/{stem}").is_err());
+        assert!(Template::parse("{mtime:%Y/%m}-{n}").is_err());
+        assert!(Template::parse("{date:%D}").is_err());
+        assert!(Template::parse("{ctime:..\\%Y}").is_err());
+    }
+
+    #[test]
+    fn test_token_values_never_add_folders() {
+        assert_eq!(render("{n}_{stem}{ext}", "/a/back\\slash.mp3", 1), "001_back_slash.mp3");
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use crate::control::{copy_with_control, RunControl};
+
+/// How a source file ends up at its destination in the output folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferMode {
+    /// Full copy, the source is left untouched.
+    #[default]
+    Copy,
+    /// Rename into the output folder, copying then deleting across file systems.
+    Move,
+    /// Second directory entry for the same data; source and output must share a file system.
+    HardLink,
+    /// Symbolic link pointing at the source file.
+    Symlink,
+    /// Copy-on-write clone where the file system supports it, a full copy otherwise.
+    Reflink,
+}
+
+impl TransferMode {
+    pub const ALL: [TransferMode; 5] = [
+        TransferMode::Copy,
+        TransferMode::Move,
+        TransferMode::HardLink,
+        TransferMode::Symlink,
+        TransferMode::Reflink,
+    ];
+
+    /// Whether the source file is gone once the transfer is done.
+    pub fn removes_source(self) -> bool {
+        self == TransferMode::Move
+    }
+}
+
+impl fmt::Display for TransferMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TransferMode::Copy => "copy",
+            TransferMode::Move => "move",
+            TransferMode::HardLink => "hardlink",
+            TransferMode::Symlink => "symlink",
+            TransferMode::Reflink => "reflink",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for TransferMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TransferMode::ALL
+            .into_iter()
+            .find(|mode| mode.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("Unknown transfer mode: {} (expected copy, move, hardlink, symlink or reflink)", s))
+    }
+}
+
+#[cfg(unix)]
+fn symlink_file(source: &Path, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, destination)
+}
+
+#[cfg(windows)]
+fn symlink_file(source: &Path, destination: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, destination)
+}
+
+fn move_file(source: &Path, destination: &Path, control: &RunControl) -> io::Result<()> {
+    match fs::rename(source, destination) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_with_control(source, destination, control)?;
+            // A source that stays behind means the move failed; the copy is not in the journal.
+            fs::remove_file(source).inspect_err(|_| {
+                let _ = fs::remove_file(destination);
+            })
+        }
+        result => result,
+    }
+}
+
+fn reflink_file(source: &Path, destination: &Path, control: &RunControl) -> io::Result<()> {
+    if reflink_copy::reflink(source, destination).is_ok() {
+        return Ok(());
+    }
+    let _ = fs::remove_file(destination);
+    copy_with_control(source, destination, control)
+}
+
+/// Puts `source` at `destination` using the given mode.
+pub fn transfer(mode: TransferMode, source: &Path, destination: &Path) -> io::Result<()> {
+    transfer_with_control(mode, source, destination, &RunControl::default())
+}
+
+/// Like [`transfer`], but copies can be paused or cancelled halfway through a file.
+pub fn transfer_with_control(mode: TransferMode, source: &Path, destination: &Path, control: &RunControl) -> io::Result<()> {
+    match mode {
+        TransferMode::Copy => copy_with_control(source, destination, control),
+        TransferMode::Move => move_file(source, destination, control),
+        TransferMode::HardLink => fs::hard_link(source, destination),
+        // Links are resolved relative to their own folder, so point at an absolute path.
+        TransferMode::Symlink => symlink_file(&fs::canonicalize(source)?, destination),
+        TransferMode::Reflink => reflink_file(source, destination, control),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_transfer_modes_produce_destination() {
+        let dir = tempdir().unwrap();
+        for mode in TransferMode::ALL {
+            let source = dir.path().join(format!("{}.mp3", mode));
+            let destination = dir.path().join(format!("{}-out.mp3", mode));
+            fs::write(&source, "data").unwrap();
+
+            transfer(mode, &source, &destination).unwrap();
+
+            assert_eq!(fs::read_to_string(&destination).unwrap(), "data");
+            assert_eq!(source.exists(), !mode.removes_source());
+        }
+    }
+
+    #[test]
+    fn test_transfer_mode_parses_display_name() {
+        for mode in TransferMode::ALL {
+            assert_eq!(mode.to_string().parse::<TransferMode>().unwrap(), mode);
+        }
+        assert!("teleport".parse::<TransferMode>().is_err());
+    }
+}
@@ -0,0 +1,246 @@
+//! Transliteration of new file names to ASCII, for players that cannot show
+//! Cyrillic, Greek or other non-Latin names.
+//!
+//! Cyrillic follows the selected scheme. Greek is transcribed after ISO 843
+//! in every scheme, and anything else (Latin letters with diacritics, other
+//! scripts, symbols) is reduced to its closest ASCII spelling. Characters
+//! without one, and lookalikes of `/` and `\`, become `_`.
+
+use std::fmt;
+use std::str::FromStr;
+use deunicode::deunicode_char;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transliteration {
+    /// GOST 7.79-2000 system B: reversible, e.g. `Щука` → `Shhuka`, `Объём` → `Ob``yom`.
+    Gost,
+    /// ISO 9:1995 with its diacritics dropped: one letter per letter, so
+    /// names keep their length, but some letters merge (`ж` and `з` give `z`).
+    Iso9,
+    /// Easy-to-read spelling as used in passports, e.g. `Щука` → `Shchuka`.
+    #[default]
+    Phonetic,
+}
+
+impl Transliteration {
+    pub const ALL: [Transliteration; 3] = [Transliteration::Gost, Transliteration::Iso9, Transliteration::Phonetic];
+
+    /// `text` spelled in ASCII. ASCII characters are kept as they are.
+    pub fn apply(self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            i += 1;
+            if c.is_ascii() {
+                result.push(c);
+                continue;
+            }
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            let latin = if lower == 'ο' && next.is_some_and(|n| matches!(n, 'υ' | 'ύ' | 'Υ' | 'Ύ')) {
+                i += 1;
+                Some("ou")
+            } else {
+                self.cyrillic(lower, next).or_else(|| greek(lower))
+            };
+            match latin {
+                Some(latin) if lower != c => {
+                    let previous = start.checked_sub(1).and_then(|p| chars.get(p));
+                    let all_caps = match chars.get(i).filter(|n| n.is_alphabetic()) {
+                        Some(n) => n.is_uppercase(),
+                        None => previous.is_some_and(|p| p.is_uppercase()),
+                    };
+                    if all_caps {
+                        result.push_str(&latin.to_uppercase());
+                    } else {
+                        let mut letters = latin.chars();
+                        result.extend(letters.next().map(|first| first.to_ascii_uppercase()));
+                        result.extend(letters);
+                    }
+                }
+                Some(latin) => result.push_str(latin),
+                // Lookalikes such as `／` must not turn into a real path separator.
+                None => match deunicode_char(c).map(str::trim) {
+                    Some(ascii) if !ascii.contains(['/', '\\']) => result.push_str(ascii),
+                    _ => result.push('_'),
+                },
+            }
+        }
+        result
+    }
+
+    // Russian, Ukrainian, Belarusian, Serbian and Macedonian letters, lowercase.
+    fn cyrillic(self, c: char, next: Option<char>) -> Option<&'static str> {
+        let latin = match (self, c) {
+            (_, 'а') => "a",
+            (_, 'б') => "b",
+            (_, 'в') => "v",
+            (_, 'г') => "g",
+            (_, 'д') => "d",
+            (_, 'е') => "e",
+            (_, 'з') => "z",
+            (_, 'и' | 'і') => "i",
+            (_, 'к') => "k",
+            (_, 'л') => "l",
+            (_, 'м') => "m",
+            (_, 'н') => "n",
+            (_, 'о') => "o",
+            (_, 'п') => "p",
+            (_, 'р') => "r",
+            (_, 'с') => "s",
+            (_, 'т') => "t",
+            (_, 'у' | 'ў') => "u",
+            (_, 'ф') => "f",
+            (_, 'ґ') => "g",
+
+            (Transliteration::Gost, 'ё') => "yo",
+            (Transliteration::Gost, 'ж') => "zh",
+            (Transliteration::Gost, 'й' | 'ј') => "j",
+            (Transliteration::Gost, 'х') => "x",
+            // `c` before the letters i, e, y and j, `cz` everywhere else.
+            (Transliteration::Gost, 'ц') => {
+                let next = next.map(|n| n.to_lowercase().next().unwrap_or(n));
+                let soft = next
+                    .and_then(|n| self.cyrillic(n, None))
+                    .is_some_and(|latin| latin.starts_with(['i', 'e', 'y', 'j']));
+                if soft { "c" } else { "cz" }
+            }
+            (Transliteration::Gost, 'ч') => "ch",
+            (Transliteration::Gost, 'ш') => "sh",
+            (Transliteration::Gost, 'щ') => "shh",
+            (Transliteration::Gost, 'ъ') => "``",
+            (Transliteration::Gost, 'ы') => "y`",
+            (Transliteration::Gost, 'ь') => "`",
+            (Transliteration::Gost, 'э') => "e`",
+            (Transliteration::Gost, 'ю') => "yu",
+            (Transliteration::Gost, 'я') => "ya",
+            (Transliteration::Gost, 'є') => "ye",
+            (Transliteration::Gost, 'ї') => "yi",
+            (Transliteration::Gost, 'ѓ') => "g`",
+            (Transliteration::Gost, 'ќ') => "k`",
+            (Transliteration::Gost, 'ѕ') => "z`",
+            (Transliteration::Gost, 'љ') => "l`",
+            (Transliteration::Gost, 'њ') => "n`",
+
+            (Transliteration::Iso9, 'ё' | 'э' | 'є') => "e",
+            (Transliteration::Iso9, 'ж' | 'ѕ') => "z",
+            (Transliteration::Iso9, 'й' | 'ј') => "j",
+            (Transliteration::Iso9, 'х') => "h",
+            (Transliteration::Iso9, 'ц' | 'ч' | 'ћ') => "c",
+            (Transliteration::Iso9, 'ш' | 'щ') => "s",
+            (Transliteration::Iso9, 'ъ' | 'ь') => "",
+            (Transliteration::Iso9, 'ы') => "y",
+            (Transliteration::Iso9, 'ю') => "u",
+            (Transliteration::Iso9, 'я') => "a",
+            (Transliteration::Iso9, 'ї') => "i",
+            (Transliteration::Iso9, 'ѓ') => "g",
+            (Transliteration::Iso9, 'ќ') => "k",
+            (Transliteration::Iso9, 'ђ' | 'џ') => "d",
+            (Transliteration::Iso9, 'љ') => "l",
+            (Transliteration::Iso9, 'њ') => "n",
+
+            (Transliteration::Phonetic, 'ё') => "yo",
+            (Transliteration::Phonetic, 'ж') => "zh",
+            (Transliteration::Phonetic, 'й') => "y",
+            (Transliteration::Phonetic, 'ј') => "j",
+            (Transliteration::Phonetic, 'х') => "kh",
+            (Transliteration::Phonetic, 'ц') => "ts",
+            (Transliteration::Phonetic, 'ч') => "ch",
+            (Transliteration::Phonetic, 'ш') => "sh",
+            (Transliteration::Phonetic, 'щ') => "shch",
+            (Transliteration::Phonetic, 'ъ' | 'ь') => "",
+            (Transliteration::Phonetic, 'ы') => "y",
+            (Transliteration::Phonetic, 'э') => "e",
+            (Transliteration::Phonetic, 'ю') => "yu",
+            (Transliteration::Phonetic, 'я') => "ya",
+            (Transliteration::Phonetic, 'є') => "ye",
+            (Transliteration::Phonetic, 'ї') => "yi",
+            (Transliteration::Phonetic, 'ѓ') => "gj",
+            (Transliteration::Phonetic, 'ќ') => "kj",
+            (Transliteration::Phonetic, 'ѕ' | 'џ') => "dz",
+            (Transliteration::Phonetic, 'ђ') => "dj",
+            (Transliteration::Phonetic, 'ћ') => "c",
+            (Transliteration::Phonetic, 'љ') => "lj",
+            (Transliteration::Phonetic, 'њ') => "nj",
+            _ => return None,
+        };
+        Some(latin)
+    }
+}
+
+// Greek letters, lowercase, after ISO 843 with accents dropped.
+fn greek(c: char) -> Option<&'static str> {
+    let latin = match c {
+        'α' | 'ά' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' | 'έ' => "e",
+        'ζ' => "z",
+        'η' | 'ή' | 'ι' | 'ί' | 'ϊ' | 'ΐ' => "i",
+        'θ' => "th",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' | 'ό' | 'ω' | 'ώ' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' | 'ύ' | 'ϋ' | 'ΰ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        _ => return None,
+    };
+    Some(latin)
+}
+
+impl fmt::Display for Transliteration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transliteration::Gost => "gost",
+            Transliteration::Iso9 => "iso9",
+            Transliteration::Phonetic => "phonetic",
+        })
+    }
+}
+
+impl FromStr for Transliteration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Transliteration::ALL
+            .into_iter()
+            .find(|scheme| scheme.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("Unknown transliteration: {} (expected gost, iso9 or phonetic)", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemes_spell_cyrillic_in_ascii() {
+        assert_eq!(Transliteration::Gost.apply("01 Щука и Цирк.mp3"), "01 Shhuka i Cirk.mp3");
+        assert_eq!(Transliteration::Gost.apply("Объём, Цвет"), "Ob``yom, Czvet");
+        assert_eq!(Transliteration::Iso9.apply("Жизнь"), "Zizn");
+        assert_eq!(Transliteration::Phonetic.apply("ЩИ и Щука"), "SHCHI i Shchuka");
+        assert_eq!(Transliteration::Phonetic.apply("Їжак та ґанок"), "Yizhak ta ganok");
+    }
+
+    #[test]
+    fn test_greek_and_other_scripts() {
+        assert_eq!(Transliteration::Phonetic.apply("Θεσσαλονίκη - Ούζο"), "Thessaloniki - Ouzo");
+        assert_eq!(Transliteration::Gost.apply("Café Straße"), "Cafe Strasse");
+        assert_eq!(Transliteration::Phonetic.apply("AC／DC ∕ ⧵"), "AC_DC _ _");
+        assert_eq!("gost".parse::<Transliteration>(), Ok(Transliteration::Gost));
+        assert!("latin".parse::<Transliteration>().is_err());
+    }
+}
@@ -1,30 +1,41 @@
 use iced::widget::{button, row, column, text, container, text_input, progress_bar};
-use iced::{Element, Length, Task};
+use iced::{task, Element, Length, Task};
 use dirs_next::home_dir;
-use std::path::{PathBuf};
+use std::path::PathBuf;
 use iced::widget::{PickList};
 use iced::widget::Checkbox;
 use iced::widget::scrollable;
+use std::time::Duration;
 
 const PADDING_OPTIONS: [usize; 5] = [1, 2, 3, 4, 5];
+const TYPING_DEBOUNCE: Duration = Duration::from_millis(300);
 
-use crate::file_ops::{
-    list_files_in_directory,
-    rename_files_with_leading_zeros,
-};
+use renamer::file_ops::display_path;
+use renamer::device::default_devices_file;
+use renamer::journal::default_journal_dir;
+use renamer::{ClockOffsets, Counter, DeviceProfile, FileFilter, FsProfile, InputMerge, Normalization, Numbering, RenameJob, RenamePlan, RunControl, SortKey, SortOrder, TagWriting, Template, TransferMode, Transliteration, UndoReport};
 
 use crate::tasks::{
+    execute_plan_with_progress,
     folder_selection,
-    perform_renaming_with_progress,
+    scan_preview,
+    undo_last_run,
 };
 
 pub struct State {
     pub folder_selector_opened: bool,
-    pub input_folder_path: Option<String>,
-    pub output_folder_path: Option<String>,
+    pub input_folders: Vec<PathBuf>,
+    pub input_merge: InputMerge,
+    pub output_folder_path: Option<PathBuf>,
     pub default_home_dir: PathBuf,
     pub status_message: String,
+    // How the last run or undo ended, kept apart from the status line so the
+    // scan that follows it does not wipe it out.
+    pub run_outcome: Option<String>,
     pub file_extension: String,
+    pub no_extension: bool,
+    pub include_patterns: String,
+    pub exclude_patterns: String,
     pub total_files: usize,
     pub renamed_count: usize,
     pub renaming_in_progress: bool,
@@ -32,18 +43,64 @@ pub struct State {
     pub renamed_preview: Vec<String>,
     pub padding_zeros: usize,
     pub include_original_name: bool,
+    pub template: String,
+    pub counter: Counter,
+    pub counter_start_input: String,
+    pub counter_step_input: String,
+    pub template_error: Option<String>,
     pub auto_padding: bool,
+    pub sort: SortOrder,
+    pub clock_offsets: ClockOffsets,
+    pub clock_offsets_input: String,
+    pub preserve_tree: bool,
+    pub write_tags: bool,
+    pub tag_writing: TagWriting,
+    pub transliterate: bool,
+    pub transliteration: Transliteration,
+    pub sanitize: bool,
+    pub fs_profile: FsProfile,
+    pub normalization: Normalization,
+    pub use_device: bool,
+    pub devices: Vec<DeviceProfile>,
+    pub device_index: usize,
+    pub devices_file: Option<PathBuf>,
+    pub device_files_input: String,
+    pub device_depth_input: String,
+    pub in_place: bool,
+    pub transfer_mode: TransferMode,
+    pub journal_dir: Option<PathBuf>,
+    pub run_control: Option<RunControl>,
+    pub paused: bool,
+    pub cancelled_files: Vec<String>,
+    pub scanning: bool,
+    pub scan_generation: u64,
+    pub scan_handle: Option<task::Handle>,
+    pub preview_plan: Option<RenamePlan>,
+    pub pending_plan: Option<RenamePlan>,
+    pub plan_report: Vec<String>,
 }
 
 impl Default for State {
     fn default() -> Self {
+        let devices_file = default_devices_file();
+        let devices = devices_file.as_deref()
+            .and_then(|path| DeviceProfile::load(path).ok())
+            .filter(|devices| !devices.is_empty())
+            .unwrap_or_else(DeviceProfile::built_in);
+        let device_files_input = limit_input(devices[0].max_files_per_folder);
+        let device_depth_input = limit_input(devices[0].max_depth);
         Self {
             folder_selector_opened: false,
-            input_folder_path: None,
+            input_folders: vec![],
+            input_merge: InputMerge::Concatenate,
             output_folder_path: None,
             default_home_dir: home_dir().unwrap_or_else(|| PathBuf::from("/")),
             status_message: "".into(),
+            run_outcome: None,
             file_extension: "mp3".into(),
+            no_extension: false,
+            include_patterns: String::new(),
+            exclude_patterns: String::new(),
             total_files: 0,
             renamed_count: 0,
             renaming_in_progress: false,
@@ -51,7 +108,41 @@ impl Default for State {
             renamed_preview: vec![],
             padding_zeros: 3,
             include_original_name: true,
+            template: String::new(),
+            counter: Counter::default(),
+            counter_start_input: "1".into(),
+            counter_step_input: "1".into(),
+            template_error: None,
             auto_padding: true,
+            sort: SortOrder::default(),
+            clock_offsets: ClockOffsets::default(),
+            clock_offsets_input: String::new(),
+            preserve_tree: false,
+            write_tags: false,
+            tag_writing: TagWriting::default(),
+            transliterate: false,
+            transliteration: Transliteration::default(),
+            sanitize: false,
+            fs_profile: FsProfile::default(),
+            normalization: Normalization::default(),
+            use_device: false,
+            devices,
+            device_index: 0,
+            device_files_input,
+            device_depth_input,
+            devices_file,
+            in_place: false,
+            transfer_mode: TransferMode::Copy,
+            journal_dir: default_journal_dir(),
+            run_control: None,
+            paused: false,
+            cancelled_files: vec![],
+            scanning: false,
+            scan_generation: 0,
+            scan_handle: None,
+            preview_plan: None,
+            pending_plan: None,
+            plan_report: vec![],
         }
     }
 }
@@ -60,75 +151,219 @@ impl Default for State {
 pub enum Message {
     FindInputFolder,
     FindOutputFolder,
-    InputFolderPathed(String),
-    OutputFolderPathed(String),
+    InputFolderPathed(Option<PathBuf>),
+    RemoveInputFolder(usize),
+    MoveInputFolderUp(usize),
+    InputMergeChanged(InputMerge),
+    OutputFolderPathed(Option<PathBuf>),
     StartRenaming,
+    ConfirmRenaming,
+    CancelRenaming,
     RenamingDone(Result<Vec<String>, String>),
     ExtensionChanged(String),
+    NoExtensionChanged(bool),
+    IncludePatternsChanged(String),
+    ExcludePatternsChanged(String),
     RenamingProgress(usize, usize),
+    PauseRenaming,
+    ResumeRenaming,
+    StopRenaming,
+    RenamingCancelled(Vec<String>),
     PaddingChanged(usize),
     IncludeOriginalNameChanged(bool),
+    TemplateChanged(String),
+    CounterStartChanged(String),
+    CounterStepChanged(String),
+    DescendingChanged(bool),
+    NumberingChanged(Numbering),
     SetAutoPadding(bool),
+    SortKeyChanged(SortKey),
+    SortDescendingChanged(bool),
+    ClockOffsetsChanged(String),
+    PreserveTreeChanged(bool),
+    WriteTagsChanged(bool),
+    WriteTotalTracksChanged(bool),
+    WriteTitleChanged(bool),
+    TransliterateChanged(bool),
+    TransliterationChanged(Transliteration),
+    SanitizeChanged(bool),
+    FsProfileChanged(FsProfile),
+    NormalizationChanged(Normalization),
+    UseDeviceChanged(bool),
+    DeviceSelected(DeviceProfile),
+    DeviceFilesPerFolderChanged(String),
+    DeviceDepthChanged(String),
+    DeviceAsciiChanged(bool),
+    DeviceShortNamesChanged(bool),
+    DeviceFsChanged(FsProfile),
+    SaveDevices,
+    InPlaceChanged(bool),
+    TransferModeChanged(TransferMode),
+    UndoLastRun,
+    UndoDone(Result<UndoReport, String>),
+    ScanProgress(u64, usize),
+    ScanFinished(u64, Result<RenamePlan, String>),
 }
 
-fn compute_auto_padding(total_files: usize) -> usize {
-    if total_files == 0 {
-        3 // default minimum padding
-    } else {
-        (total_files as f64).log10().ceil() as usize + 1
+// Device limits are typed as numbers; an empty field means no limit.
+fn limit_input(limit: Option<usize>) -> String {
+    limit.map(|n| n.to_string()).unwrap_or_default()
+}
+
+// `None` while the field holds something other than a number.
+fn parse_limit_input(value: &str) -> Option<Option<usize>> {
+    match value.trim() {
+        "" => Some(None),
+        digits => digits.parse().ok().map(Some),
     }
 }
 
-fn to_display_string(path: &PathBuf) -> String {
-    match path.to_str() {
-        Some(valid) => valid.to_string(), // Safe UTF-8 path
-        None => path.to_string_lossy().into_owned(), // Fall back to lossy conversion
+// Patterns are typed separated by `;`, since globs may contain commas.
+fn split_patterns(patterns: &str) -> impl Iterator<Item = &str> {
+    patterns.split(';').map(str::trim).filter(|p| !p.is_empty())
+}
+
+// Expects at least one input folder.
+fn build_job(state: &State) -> RenameJob {
+    let inputs = &state.input_folders;
+    let mut job = RenameJob::new(&inputs[0], state.output_folder_path.clone().unwrap_or_default())
+        .input_merge(state.input_merge)
+        .extension(&state.file_extension)
+        .no_extension(state.no_extension)
+        .include_original_name(state.include_original_name)
+        .in_place(state.in_place)
+        .transfer_mode(state.transfer_mode)
+        .counter(state.counter)
+        .sort(state.sort)
+        .clock_offsets(state.clock_offsets.clone())
+        .normalization(state.normalization)
+        .preserve_tree(state.preserve_tree);
+    for input in &inputs[1..] {
+        job = job.add_input(input);
+    }
+    for pattern in split_patterns(&state.include_patterns) {
+        job = job.include_pattern(pattern);
+    }
+    for pattern in split_patterns(&state.exclude_patterns) {
+        job = job.exclude_pattern(pattern);
+    }
+    if !state.template.trim().is_empty() {
+        job = job.template(&state.template);
+    }
+    if let Some(dir) = &state.journal_dir {
+        job = job.journal_dir(dir);
+    }
+    if state.write_tags {
+        job = job.write_tags(state.tag_writing);
+    }
+    if state.use_device
+        && let Some(device) = state.devices.get(state.device_index)
+    {
+        job = job.device(device.clone());
+    }
+    if state.transliterate {
+        job = job.transliterate(state.transliteration);
+    }
+    if state.sanitize {
+        job = job.fs_profile(state.fs_profile);
+    }
+    if state.auto_padding {
+        job
+    } else {
+        job.padding_zeros(state.padding_zeros)
     }
 }
 
-fn update_preview(state: &mut State) {
-    if let Some(input_path) = &state.input_folder_path {
-        let ext = &state.file_extension;
-        match list_files_in_directory(input_path, ext) {
-            Ok(files) => {
-                state.total_files = files.len();
+fn update_preview(state: &mut State) -> Task<Message> {
+    refresh_preview(state, Duration::ZERO)
+}
 
-                if state.auto_padding {
-                    state.padding_zeros = compute_auto_padding(state.total_files);
-                }
+// Starts a background scan, abandoning any scan still running. Results of
+// older scans are told apart by their generation and ignored.
+fn refresh_preview(state: &mut State, debounce: Duration) -> Task<Message> {
+    state.pending_plan = None;
+    state.plan_report.clear();
+    if state.input_folders.is_empty() {
+        return Task::none();
+    }
+    if let Some(handle) = state.scan_handle.take() {
+        handle.abort();
+    }
 
-                if files.is_empty() {
-                    state.status_message = format!("No files with extension .{} found in input folder.", ext);
-                    state.original_preview.clear();
-                    state.renamed_preview.clear();
-                } else {
-                    let renamed_names = rename_files_with_leading_zeros(&files, state.padding_zeros, state.include_original_name);
+    state.scan_generation += 1;
+    state.scanning = true;
+    state.preview_plan = None;
+    state.status_message = "Scanning… 0 files found".into();
 
-                    state.original_preview = files.iter()
-                        .map(to_display_string)
-                        .take(20)
-                        .collect();
+    let job = build_job(state);
+    let (task, handle) = Task::stream(scan_preview(job, state.scan_generation, debounce)).abortable();
+    state.scan_handle = Some(handle);
+    task
+}
 
-                    let output_dir = PathBuf::from(&state.output_folder_path.clone().unwrap_or_default());
+// A field holds something that cannot be used: the preview is dropped and
+// any scan still running is abandoned, so no plan made with the settings
+// from before can be started while the field shows something else.
+fn discard_preview(state: &mut State, message: String) -> Task<Message> {
+    if let Some(handle) = state.scan_handle.take() {
+        handle.abort();
+    }
+    state.scan_generation += 1;
+    state.scanning = false;
+    state.preview_plan = None;
+    state.pending_plan = None;
+    state.plan_report.clear();
+    state.status_message = message;
+    Task::none()
+}
 
-                    state.renamed_preview = renamed_names
-                        .iter()
-                        .map(|name| to_display_string(&output_dir.join(name)))
-                        .take(20)
-                        .collect();
+fn show_preview(state: &mut State, result: Result<RenamePlan, String>) {
+    let mut kinds = FileFilter::extensions(&state.file_extension).describe();
+    if state.no_extension {
+        kinds = if kinds.is_empty() { "no extension".into() } else { format!("{}, no extension", kinds) };
+    }
+    match result {
+        Ok(plan) => {
+            state.total_files = plan.len();
+            state.padding_zeros = plan.padding_zeros;
 
-                    state.status_message = format!("Found {} files (preview limited to 20).", files.len());
-                }
-            }
-            Err(e) => {
-                state.status_message = format!("Error reading input folder: {}", e);
-                state.total_files = 0;
+            if plan.is_empty() {
+                state.status_message = format!("No matching files ({}) found in input folder.", kinds);
                 state.original_preview.clear();
                 state.renamed_preview.clear();
+            } else {
+                state.original_preview = plan.entries.iter()
+                    .map(|entry| display_path(&entry.source))
+                    .take(20)
+                    .collect();
+
+                state.renamed_preview = plan.entries.iter()
+                    .map(|entry| match entry.notes.is_empty() {
+                        true => display_path(&entry.destination),
+                        false => format!("{}  ⚠ {}", display_path(&entry.destination), entry.notes.join("; ")),
+                    })
+                    .take(20)
+                    .collect();
+
+                let flagged = plan.entries.iter().filter(|entry| !entry.notes.is_empty()).count();
+                state.status_message = if flagged == 0 {
+                    format!("Found {} files (preview limited to 20).", plan.len())
+                } else {
+                    format!("Found {} files, {} flagged (preview limited to 20).", plan.len(), flagged)
+                };
+                for warning in &plan.warnings {
+                    state.status_message.push_str(&format!("\n⚠ {}", warning));
+                }
             }
-        };
-       
-    }
+            state.preview_plan = Some(plan);
+        }
+        Err(e) => {
+            state.status_message = format!("Error reading input folder: {}", e);
+            state.total_files = 0;
+            state.original_preview.clear();
+            state.renamed_preview.clear();
+        }
+    };
 }
 
 
@@ -138,9 +373,9 @@ pub fn update(state: &mut State, message: Message) -> Task<Message> {
         Message::FindInputFolder => {
             if !state.folder_selector_opened {
                 state.folder_selector_opened = true;
-                let dir = state.input_folder_path
-                    .as_ref()
-                    .map_or(state.default_home_dir.clone(), |p| PathBuf::from(p));
+                let dir = state.input_folders
+                    .last()
+                    .map_or(state.default_home_dir.clone(), PathBuf::clone);
                 Task::perform(folder_selection(dir), Message::InputFolderPathed)
             } else {
                 Task::none()
@@ -150,103 +385,194 @@ pub fn update(state: &mut State, message: Message) -> Task<Message> {
             if !state.folder_selector_opened {
                 state.folder_selector_opened = true;
                 let dir = state.output_folder_path
-                    .as_ref()
-                    .map_or(state.default_home_dir.clone(), |p| PathBuf::from(p));
+                    .clone()
+                    .unwrap_or_else(|| state.default_home_dir.clone());
                 Task::perform(folder_selection(dir), Message::OutputFolderPathed)
             } else {
                 Task::none()
             }
         }
-        Message::InputFolderPathed(path) => {
-            if !path.is_empty() {
-                let new_input = path.to_string();
-                state.input_folder_path = Some(new_input.clone());
-                state.output_folder_path = Some(PathBuf::from(new_input.clone()).join("output").to_string_lossy().to_string());
-
-                let ext = &state.file_extension;
-                match list_files_in_directory(&new_input, ext) {
-                    Ok(files) => {
-                        state.total_files = files.len();
-                        if files.is_empty() {
-                            state.status_message = format!("No files with extension .{} found in input folder.", ext);
-                            state.original_preview.clear();
-                            state.renamed_preview.clear();
-                        } else {
-                            let renamed_names = rename_files_with_leading_zeros(&files, state.padding_zeros, state.include_original_name);
-
-                            state.original_preview = files.iter()
-                                .map(|p| p.to_string_lossy().to_string())
-                                .take(20)
-                                .collect();
-
-                            let output_dir = PathBuf::from(&state.output_folder_path.clone().unwrap_or_default());
-
-                            state.renamed_preview = renamed_names
-                                .iter()
-                                .map(|name| output_dir.join(name).to_string_lossy().to_string())
-                                .take(20)
-                                .collect();
-
-                            state.status_message = format!("Found {} files (preview limited to 20).", files.len());
-                        }
-                    }
-                    Err(e) => {
-                        state.status_message = format!("Error reading input folder: {}", e);
-                        state.total_files = 0;
-                        state.original_preview.clear();
-                        state.renamed_preview.clear();
-                    }
-                };
+        Message::InputFolderPathed(Some(path)) => {
+            if !state.input_folders.contains(&path) {
+                if state.input_folders.is_empty() {
+                    state.output_folder_path = Some(path.join("output"));
+                }
+                state.input_folders.push(path);
+
+                state.folder_selector_opened = false;
+                return update_preview(state);
             }
             state.folder_selector_opened = false;
             Task::none()
         }
-        Message::OutputFolderPathed(path) => {
-            if !path.is_empty() {
-                state.output_folder_path = Some(path);
+        Message::InputFolderPathed(None) => {
+            state.folder_selector_opened = false;
+            Task::none()
+        }
+        Message::RemoveInputFolder(index) => {
+            if index < state.input_folders.len() {
+                state.input_folders.remove(index);
             }
+            if state.input_folders.is_empty() {
+                if let Some(handle) = state.scan_handle.take() {
+                    handle.abort();
+                }
+                state.scanning = false;
+                state.preview_plan = None;
+                state.total_files = 0;
+                state.original_preview.clear();
+                state.renamed_preview.clear();
+                state.status_message.clear();
+                return Task::none();
+            }
+            update_preview(state)
+        }
+        Message::MoveInputFolderUp(index) => {
+            if index > 0 && index < state.input_folders.len() {
+                state.input_folders.swap(index - 1, index);
+            }
+            update_preview(state)
+        }
+        Message::InputMergeChanged(merge) => {
+            state.input_merge = merge;
+            update_preview(state)
+        }
+        Message::OutputFolderPathed(path) => {
             state.folder_selector_opened = false;
+            if path.is_some() {
+                state.output_folder_path = path;
+                return update_preview(state);
+            }
             Task::none()
         }
         Message::StartRenaming => {
-            if state.input_folder_path.is_none() || state.output_folder_path.is_none() {
+            if state.input_folders.is_empty() {
                 state.status_message = "Please select both input and output folders before starting.".into();
-                Task::none()
+                return Task::none();
+            }
+            if state.output_folder_path.is_none() && !state.in_place {
+                state.status_message = "Please select both input and output folders before starting.".into();
+                return Task::none();
+            }
+            let verb = if state.in_place {
+                "renamed in place"
             } else {
-                state.renaming_in_progress = true;
-                state.renamed_count = 0;
-                state.total_files = 0;
+                match state.transfer_mode {
+                    TransferMode::Copy | TransferMode::Reflink => "copied",
+                    TransferMode::Move => "moved",
+                    TransferMode::HardLink | TransferMode::Symlink => "linked",
+                }
+            };
 
-                let input = state.input_folder_path.clone();
-                let output = state.output_folder_path.clone();
-                let ext = state.file_extension.clone();
-
-                Task::stream(perform_renaming_with_progress(
-                    input,
-                    output,
-                    ext,
-                    state.padding_zeros,
-                    state.include_original_name
-                ))
+            if state.scanning {
+                state.status_message = "Please wait for the scan of the input folder to finish.".into();
+                return Task::none();
             }
-        }
-        Message::RenamingDone(result) => {
-            state.renaming_in_progress = false;
-            match result {
-                Ok(files) => {
-                    state.status_message = format!("Renaming complete! {} files renamed.", files.len());
+            match state.preview_plan.clone() {
+                Some(plan) if plan.is_empty() => {
+                    state.status_message = "No files found to rename.".into();
                 }
-                Err(e) => {
-                    state.status_message = format!("Error: {}", e);
+                Some(plan) => {
+                    let report = plan.check();
+                    state.plan_report = report.conflicts.iter().map(|c| c.to_string()).collect();
+                    state.status_message = if report.is_clean() {
+                        format!("{} files will be {}. No conflicts found.", plan.len(), verb)
+                    } else {
+                        format!("{} files will be {}. {} conflicts found, existing files will be overwritten.", plan.len(), verb, report.conflicts.len())
+                    };
+                    state.pending_plan = Some(plan);
                 }
+                // The status line already tells why there is no plan: a field
+                // that cannot be used, or an input folder that could not be read.
+                None => {}
             }
             Task::none()
         }
+        Message::ConfirmRenaming => {
+            let Some(plan) = state.pending_plan.take() else {
+                return Task::none();
+            };
+            state.plan_report.clear();
+            state.cancelled_files.clear();
+            state.run_outcome = None;
+            state.renaming_in_progress = true;
+            state.paused = false;
+            state.renamed_count = 0;
+            state.total_files = plan.len();
+
+            let control = RunControl::default();
+            state.run_control = Some(control.clone());
+            Task::stream(execute_plan_with_progress(plan, control))
+        }
+        Message::CancelRenaming => {
+            state.pending_plan = None;
+            state.plan_report.clear();
+            state.status_message = "Renaming cancelled, no files were touched.".into();
+            Task::none()
+        }
+        Message::RenamingDone(result) => {
+            state.renaming_in_progress = false;
+            state.run_control = None;
+            state.run_outcome = Some(match result {
+                Ok(files) => format!("Renaming complete! {} files renamed.", files.len()),
+                Err(e) => format!("Error: {}", e),
+            });
+            update_preview(state)
+        }
         Message::ExtensionChanged(ext) => {
             state.file_extension = ext.trim_start_matches('.').to_string();
-            update_preview(state);
+            refresh_preview(state, TYPING_DEBOUNCE)
+        }
+        Message::NoExtensionChanged(include) => {
+            state.no_extension = include;
+            update_preview(state)
+        }
+        Message::IncludePatternsChanged(patterns) => {
+            state.include_patterns = patterns;
+            refresh_preview(state, TYPING_DEBOUNCE)
+        }
+        Message::ExcludePatternsChanged(patterns) => {
+            state.exclude_patterns = patterns;
+            refresh_preview(state, TYPING_DEBOUNCE)
+        }
+        Message::PauseRenaming => {
+            if let Some(control) = &state.run_control {
+                control.pause();
+                state.paused = true;
+                state.status_message = format!("Paused at {}/{}", state.renamed_count, state.total_files);
+            }
             Task::none()
         }
+        Message::ResumeRenaming => {
+            if let Some(control) = &state.run_control {
+                control.resume();
+                state.paused = false;
+                state.status_message = format!("Renaming... {}/{}", state.renamed_count, state.total_files);
+            }
+            Task::none()
+        }
+        Message::StopRenaming => {
+            if let Some(control) = &state.run_control {
+                control.cancel();
+                state.status_message = "Cancelling...".into();
+            }
+            Task::none()
+        }
+        Message::RenamingCancelled(completed) => {
+            state.renaming_in_progress = false;
+            state.run_control = None;
+            state.paused = false;
+            state.run_outcome = Some(if completed.is_empty() {
+                "Renaming cancelled, no files were changed.".into()
+            } else {
+                format!("Renaming cancelled after {} files. Roll back to remove them, or keep them.", completed.len())
+            });
+            // The rescan clears the report, so the completed files go in after it.
+            let scan = update_preview(state);
+            state.plan_report = completed.clone();
+            state.cancelled_files = completed;
+            scan
+        }
         Message::RenamingProgress(done, total) => {
             state.renamed_count = done;
             state.total_files = total;
@@ -255,34 +581,246 @@ pub fn update(state: &mut State, message: Message) -> Task<Message> {
         },
         Message::PaddingChanged(value) => {
             state.padding_zeros = value;
-            update_preview(state);
-            Task::none()
+            update_preview(state)
         },
 
         Message::IncludeOriginalNameChanged(include) => {
             state.include_original_name = include;
-            update_preview(state);
-            Task::none()
+            update_preview(state)
+        },
+        Message::TemplateChanged(template) => {
+            state.template_error = if template.trim().is_empty() {
+                None
+            } else {
+                Template::parse(&template).err()
+            };
+            state.template = template;
+            if let Some(e) = &state.template_error {
+                let message = format!("Invalid template: {}", e);
+                discard_preview(state, message)
+            } else {
+                refresh_preview(state, TYPING_DEBOUNCE)
+            }
+        },
+        Message::ClockOffsetsChanged(value) => {
+            let parsed = value.parse::<ClockOffsets>();
+            state.clock_offsets_input = value;
+            match parsed {
+                Ok(offsets) => {
+                    state.clock_offsets = offsets;
+                    refresh_preview(state, TYPING_DEBOUNCE)
+                }
+                Err(e) => discard_preview(state, e),
+            }
+        },
+        Message::CounterStartChanged(value) => {
+            state.counter_start_input = value.clone();
+            match value.trim().parse::<u64>() {
+                Ok(start) => {
+                    state.counter.start = start;
+                    refresh_preview(state, TYPING_DEBOUNCE)
+                }
+                Err(_) => discard_preview(state, "The start number must be a whole number, 0 or more.".into()),
+            }
+        },
+        Message::CounterStepChanged(value) => {
+            state.counter_step_input = value.clone();
+            match value.trim().parse::<u64>().ok().filter(|step| *step > 0) {
+                Some(step) => {
+                    state.counter.step = step;
+                    refresh_preview(state, TYPING_DEBOUNCE)
+                }
+                None => discard_preview(state, "The step must be a whole number, 1 or more.".into()),
+            }
+        },
+        Message::DescendingChanged(descending) => {
+            state.counter.descending = descending;
+            update_preview(state)
+        },
+        Message::NumberingChanged(numbering) => {
+            state.counter.numbering = numbering;
+            update_preview(state)
         },
         Message::SetAutoPadding(auto) => {
             state.auto_padding = auto;
-            update_preview(state);
+            update_preview(state)
+        },
+        Message::SortKeyChanged(key) => {
+            state.sort.key = key;
+            update_preview(state)
+        },
+        Message::SortDescendingChanged(descending) => {
+            state.sort.descending = descending;
+            update_preview(state)
+        },
+        Message::PreserveTreeChanged(preserve) => {
+            state.preserve_tree = preserve;
+            update_preview(state)
+        },
+        Message::WriteTagsChanged(write) => {
+            state.write_tags = write;
+            update_preview(state)
+        },
+        Message::WriteTotalTracksChanged(total) => {
+            state.tag_writing.total_tracks = total;
+            update_preview(state)
+        },
+        Message::WriteTitleChanged(title) => {
+            state.tag_writing.title = title;
+            update_preview(state)
+        },
+        Message::TransliterateChanged(transliterate) => {
+            state.transliterate = transliterate;
+            update_preview(state)
+        },
+        Message::TransliterationChanged(scheme) => {
+            state.transliteration = scheme;
+            state.transliterate = true;
+            update_preview(state)
+        },
+        Message::SanitizeChanged(sanitize) => {
+            state.sanitize = sanitize;
+            update_preview(state)
+        },
+        Message::FsProfileChanged(profile) => {
+            state.fs_profile = profile;
+            state.sanitize = true;
+            update_preview(state)
+        },
+        Message::NormalizationChanged(normalization) => {
+            state.normalization = normalization;
+            update_preview(state)
+        },
+        Message::UseDeviceChanged(use_device) => {
+            state.use_device = use_device;
+            update_preview(state)
+        },
+        Message::DeviceSelected(device) => {
+            if let Some(index) = state.devices.iter().position(|d| *d == device) {
+                state.device_index = index;
+                state.device_files_input = limit_input(device.max_files_per_folder);
+                state.device_depth_input = limit_input(device.max_depth);
+                state.use_device = true;
+            }
+            update_preview(state)
+        },
+        Message::DeviceFilesPerFolderChanged(value) => {
+            state.device_files_input = value.clone();
+            let Some(limit) = parse_limit_input(&value) else {
+                return discard_preview(state, "Device limits need a number, or nothing for no limit.".into());
+            };
+            if let Some(device) = state.devices.get_mut(state.device_index) {
+                device.max_files_per_folder = limit;
+            }
+            refresh_preview(state, TYPING_DEBOUNCE)
+        },
+        Message::DeviceDepthChanged(value) => {
+            state.device_depth_input = value.clone();
+            let Some(limit) = parse_limit_input(&value) else {
+                return discard_preview(state, "Device limits need a number, or nothing for no limit.".into());
+            };
+            if let Some(device) = state.devices.get_mut(state.device_index) {
+                device.max_depth = limit;
+            }
+            refresh_preview(state, TYPING_DEBOUNCE)
+        },
+        Message::DeviceAsciiChanged(ascii_only) => {
+            if let Some(device) = state.devices.get_mut(state.device_index) {
+                device.ascii_only = ascii_only;
+            }
+            update_preview(state)
+        },
+        Message::DeviceShortNamesChanged(short_names) => {
+            if let Some(device) = state.devices.get_mut(state.device_index) {
+                device.short_names = short_names;
+            }
+            update_preview(state)
+        },
+        Message::DeviceFsChanged(fs) => {
+            if let Some(device) = state.devices.get_mut(state.device_index) {
+                device.fs = fs;
+            }
+            update_preview(state)
+        },
+        Message::SaveDevices => {
+            state.status_message = match &state.devices_file {
+                Some(path) => match DeviceProfile::save(&state.devices, path) {
+                    Ok(()) => format!("Device profiles saved to {}.", display_path(path)),
+                    Err(e) => format!("Error saving device profiles: {}", e),
+                },
+                None => "No folder for device profiles is available.".into(),
+            };
             Task::none()
         },
+        Message::InPlaceChanged(in_place) => {
+            state.in_place = in_place;
+            update_preview(state)
+        },
+        Message::TransferModeChanged(mode) => {
+            state.transfer_mode = mode;
+            update_preview(state)
+        },
+        Message::ScanProgress(generation, found) => {
+            if generation == state.scan_generation && state.scanning {
+                state.status_message = format!("Scanning… {} files found", found);
+            }
+            Task::none()
+        }
+        Message::ScanFinished(generation, result) => {
+            if generation == state.scan_generation {
+                state.scanning = false;
+                state.scan_handle = None;
+                show_preview(state, result);
+            }
+            Task::none()
+        }
+        Message::UndoLastRun => {
+            state.cancelled_files.clear();
+            state.run_outcome = None;
+            state.status_message = "Undoing last run...".into();
+            Task::perform(undo_last_run(state.journal_dir.clone()), Message::UndoDone)
+        },
+        Message::UndoDone(result) => {
+            let scan = update_preview(state);
+            state.run_outcome = Some(match result {
+                Ok(report) if report.skipped.is_empty() => {
+                    format!("Undo complete! {} files reverted.", report.reverted)
+                }
+                Ok(report) => {
+                    let outcome = format!(
+                        "Undo finished: {} files reverted, {} left untouched.",
+                        report.reverted,
+                        report.skipped.len()
+                    );
+                    state.plan_report = report.skipped;
+                    outcome
+                }
+                Err(e) => format!("Error: {}", e),
+            });
+            scan
+        },
     }
 }
 
 // --- View function ---
 pub fn view(state: &State) -> Element<'_, Message> {
-    let input_display = state.input_folder_path
-        .as_ref()
-        .map(|s| s.as_str())
-        .unwrap_or("Click to select a folder --->");
+    let selected_device = state.devices.get(state.device_index)
+        .cloned()
+        .unwrap_or_else(|| DeviceProfile::new("", FsProfile::Fat32));
+    let input_folders = state.input_folders.iter().enumerate().fold(column![], |col, (i, folder)| {
+        col.push(
+            row![
+                text_input("", &display_path(folder)).width(Length::Fill),
+                button("↑").on_press_maybe((i > 0).then_some(Message::MoveInputFolderUp(i))),
+                button("✕").on_press(Message::RemoveInputFolder(i)),
+            ]
+            .spacing(5)
+        )
+    });
 
     let output_display = state.output_folder_path
-        .as_ref()
-        .map(|s| s.as_str())
-        .unwrap_or("Click to select a folder --->");
+        .as_deref()
+        .map_or("Click to select a folder --->".to_string(), display_path);
 
     let progress_value = if state.total_files == 0 {
         0.0
@@ -322,21 +860,78 @@ pub fn view(state: &State) -> Element<'_, Message> {
         .width(Length::Fill)
         .height(20);
 
+    let report_view = scrollable(
+        state.plan_report.iter().fold(column![], |col, line| {
+            col.push(text(line).size(14))
+        })
+    )
+    .height(Length::Shrink);
+
+    let actions = if state.renaming_in_progress {
+        row![
+            if state.paused {
+                button("Resume").on_press(Message::ResumeRenaming)
+            } else {
+                button("Pause").on_press(Message::PauseRenaming)
+            },
+            button("Cancel").on_press(Message::StopRenaming),
+        ]
+        .spacing(10)
+    } else if !state.cancelled_files.is_empty() && state.journal_dir.is_some() {
+        row![
+            button("Roll back completed files").on_press(Message::UndoLastRun),
+            button("Start renaming").on_press(Message::StartRenaming),
+        ]
+        .spacing(10)
+    } else if state.pending_plan.is_some() {
+        row![
+            button("Confirm").on_press(Message::ConfirmRenaming),
+            button("Back").on_press(Message::CancelRenaming),
+        ]
+        .spacing(10)
+    } else {
+        row![
+            button("Start renaming").on_press(Message::StartRenaming),
+            button("Undo last run").on_press(Message::UndoLastRun),
+        ]
+        .spacing(10)
+    };
+
     let main_content = column![
-        text("Input folder"),
+        text("Input folders"),
+        input_folders.spacing(5),
         row![
-            text_input("Click to select a folder --->", input_display),
+            text_input("Click to add a folder --->", ""),
             text_input(
-                "e.g. mp3",
+                "e.g. mp3,flac",
                 if state.file_extension.is_empty() { "" } else { &state.file_extension }
             )
             .on_input(Message::ExtensionChanged)
-            .width(100),
+            .width(140),
             button("+").on_press(Message::FindInputFolder),
         ],
+        row![
+            text_input("Only include, e.g. **/Disc */** (separate with ;)", &state.include_patterns)
+                .on_input(Message::IncludePatternsChanged),
+            text_input("Exclude, e.g. **/Live/**; *sample*", &state.exclude_patterns)
+                .on_input(Message::ExcludePatternsChanged),
+            Checkbox::new("Files without extension", state.no_extension)
+                .on_toggle(Message::NoExtensionChanged),
+        ]
+        .spacing(10),
+        row![
+            text("Combine folders by").size(14),
+            PickList::new(
+                &InputMerge::ALL[..],
+                Some(state.input_merge),
+                Message::InputMergeChanged,
+            )
+            .width(150),
+        ]
+        .spacing(10),
         row![ text("Output folder") ].spacing(10),
         row![
-            text_input("Click to select a folder --->", output_display),
+            text_input("Click to select a folder --->", &output_display),
             button("+").on_press(Message::FindOutputFolder),
         ],
 
@@ -355,26 +950,172 @@ pub fn view(state: &State) -> Element<'_, Message> {
             .placeholder("Padding")
             .width(100),
 
+            row![
+                text("Sort by").size(14),
+                PickList::new(
+                    &SortKey::ALL[..],
+                    Some(state.sort.key),
+                    Message::SortKeyChanged,
+                )
+                .width(150),
+                Checkbox::new("Reverse order", state.sort.descending)
+                    .on_toggle(Message::SortDescendingChanged),
+            ]
+            .spacing(10),
+
+            row![
+                text("Start at").size(14),
+                text_input("1", &state.counter_start_input)
+                    .on_input(Message::CounterStartChanged)
+                    .width(80),
+                text("Step").size(14),
+                text_input("1", &state.counter_step_input)
+                    .on_input(Message::CounterStepChanged)
+                    .width(80),
+                Checkbox::new("Descending", state.counter.descending)
+                    .on_toggle(Message::DescendingChanged),
+                text("Numbering").size(14),
+                PickList::new(
+                    &Numbering::ALL[..],
+                    Some(state.counter.numbering),
+                    Message::NumberingChanged,
+                )
+                .width(150),
+            ]
+            .spacing(10),
+
+            text_input(
+                "Naming template, e.g. {n:03}-{stem}{ext} (leave empty to use the options here)",
+                &state.template,
+            )
+            .on_input(Message::TemplateChanged),
+
+            text_input(
+                "Clock offsets, e.g. Phone=+1h; camera:Canon EOS 80D=-3m (applied to capture dates)",
+                &state.clock_offsets_input,
+            )
+            .on_input(Message::ClockOffsetsChanged),
+
             Checkbox::new(
                 "Include original name in the new filename",
                 state.include_original_name,
             )
             .on_toggle(Message::IncludeOriginalNameChanged)
             .spacing(10),
+
+            Checkbox::new(
+                "Keep subfolders of the input folder in the output",
+                state.preserve_tree,
+            )
+            .on_toggle(Message::PreserveTreeChanged)
+            .spacing(10),
+
+            row![
+                Checkbox::new("Write the number into the track tag", state.write_tags)
+                    .on_toggle(Message::WriteTagsChanged),
+                Checkbox::new("with total tracks", state.tag_writing.total_tracks)
+                    .on_toggle_maybe(state.write_tags.then_some(Message::WriteTotalTracksChanged)),
+                Checkbox::new("and a cleaned-up title", state.tag_writing.title)
+                    .on_toggle_maybe(state.write_tags.then_some(Message::WriteTitleChanged)),
+            ]
+            .spacing(10),
+
+            row![
+                Checkbox::new("Spell new names in ASCII", state.transliterate)
+                    .on_toggle(Message::TransliterateChanged),
+                PickList::new(
+                    &Transliteration::ALL[..],
+                    Some(state.transliteration),
+                    Message::TransliterationChanged,
+                )
+                .width(150),
+            ]
+            .spacing(10),
+
+            row![
+                Checkbox::new("Make names safe for the target file system", state.sanitize)
+                    .on_toggle(Message::SanitizeChanged),
+                PickList::new(
+                    &FsProfile::ALL[..],
+                    Some(state.fs_profile),
+                    Message::FsProfileChanged,
+                )
+                .width(150),
+                text("Unicode form").size(14),
+                PickList::new(
+                    &Normalization::ALL[..],
+                    Some(state.normalization),
+                    Message::NormalizationChanged,
+                )
+                .width(100),
+            ]
+            .spacing(10),
+
+            row![
+                Checkbox::new("Fit the output to a device", state.use_device)
+                    .on_toggle(Message::UseDeviceChanged),
+                PickList::new(
+                    &state.devices[..],
+                    state.devices.get(state.device_index).cloned(),
+                    Message::DeviceSelected,
+                )
+                .width(200),
+                button("Save profiles").on_press_maybe(state.devices_file.is_some().then_some(Message::SaveDevices)),
+            ]
+            .spacing(10),
+
+            row![
+                text("Files per folder").size(14),
+                text_input("any", &state.device_files_input)
+                    .on_input(Message::DeviceFilesPerFolderChanged)
+                    .width(80),
+                text("Folder depth").size(14),
+                text_input("any", &state.device_depth_input)
+                    .on_input(Message::DeviceDepthChanged)
+                    .width(80),
+                Checkbox::new("ASCII names", selected_device.ascii_only)
+                    .on_toggle(Message::DeviceAsciiChanged),
+                Checkbox::new("8.3 names", selected_device.short_names)
+                    .on_toggle(Message::DeviceShortNamesChanged),
+                PickList::new(
+                    &FsProfile::ALL[..],
+                    Some(selected_device.fs),
+                    Message::DeviceFsChanged,
+                )
+                .width(100),
+            ]
+            .spacing(10),
+
+            Checkbox::new(
+                "Rename in place (no output folder, no extra disk space)",
+                state.in_place,
+            )
+            .on_toggle(Message::InPlaceChanged)
+            .spacing(10),
+
+            row![
+                text("Transfer mode").size(14),
+                PickList::new(
+                    &TransferMode::ALL[..],
+                    Some(state.transfer_mode),
+                    Message::TransferModeChanged,
+                )
+                .width(150),
+            ]
+            .spacing(10),
         ]
         .spacing(5),
 
-        column![
-            text(&state.status_message).size(14),
-            previews,
-        ]
-        .width(Length::FillPortion(1))
-        .padding(10),
+        column![]
+            .push_maybe(state.run_outcome.as_deref().map(|outcome| text(outcome).size(14)))
+            .push(text(&state.status_message).size(14))
+            .push(previews)
+            .width(Length::FillPortion(1))
+            .padding(10),
 
-        container(
-            row![ button("Start renaming").on_press(Message::StartRenaming) ].spacing(10)
-        )
-        .center_x(Length::Fill),
+        report_view,
+
+        container(actions).center_x(Length::Fill),
 
     ]
     .spacing(10)
@@ -396,3 +1137,33 @@ pub fn view(state: &State) -> Element<'_, Message> {
     .height(Length::Fill)
     .into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_changing_a_setting_drops_the_plan_awaiting_confirmation() {
+        let input_dir = tempdir().unwrap();
+        let output_dir = tempdir().unwrap();
+        std::fs::write(input_dir.path().join("track.mp3"), "Dummy content").unwrap();
+
+        let mut state = State {
+            input_folders: vec![input_dir.path().to_path_buf()],
+            output_folder_path: Some(output_dir.path().to_path_buf()),
+            file_extension: "mp3".into(),
+            ..State::default()
+        };
+        state.preview_plan = Some(build_job(&state).plan().unwrap());
+
+        let _ = update(&mut state, Message::StartRenaming);
+        assert!(state.pending_plan.is_some());
+
+        let _ = update(&mut state, Message::PaddingChanged(5));
+        let _ = update(&mut state, Message::ConfirmRenaming);
+        assert!(state.pending_plan.is_none());
+        assert!(!state.renaming_in_progress);
+        assert_eq!(std::fs::read_dir(output_dir.path()).unwrap().count(), 0);
+    }
+}
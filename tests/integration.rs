@@ -0,0 +1,362 @@
+use std::fs::File;
+use futures::StreamExt;
+use renamer::{Counter, DeviceProfile, FsProfile, InputMerge, Progress, RenameJob, SortKey, SortOrder, TagWriting, TransferMode, Transliteration};
+use tempfile::tempdir;
+
+#[test]
+fn test_plan_lists_sources_and_destinations() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for name in ["track10.mp3", "track2.mp3", "cover.jpg"] {
+        File::create(input_dir.path().join(name)).unwrap();
+    }
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .extension(".mp3")
+        .padding_zeros(2)
+        .include_original_name(true)
+        .plan()
+        .unwrap();
+
+    assert_eq!(plan.len(), 2);
+    assert_eq!(plan.entries[0].source, input_dir.path().join("track2.mp3"));
+    assert_eq!(plan.entries[0].destination, output_dir.path().join("01_track2.mp3"));
+    assert_eq!(plan.entries[1].destination, output_dir.path().join("02_track10.mp3"));
+    assert!(!output_dir.path().join("01_track2.mp3").exists());
+}
+
+#[tokio::test]
+async fn test_run_reports_progress_without_ui() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for i in 1..=3 {
+        File::create(input_dir.path().join(format!("{}.mp3", i))).unwrap();
+    }
+
+    let events: Vec<Progress> = RenameJob::new(input_dir.path(), output_dir.path())
+        .run()
+        .collect()
+        .await;
+
+    assert_eq!(events.len(), 4);
+    let Some(Progress::Finished(Ok(written))) = events.last() else { panic!("Expected a successful run") };
+    assert_eq!(written.len(), 3);
+    assert!(written.iter().all(|p| p.exists()));
+}
+
+#[tokio::test]
+async fn test_in_place_run_handles_chained_names() {
+    let dir = tempdir().unwrap();
+    std::fs::write(dir.path().join("002.mp3"), "first").unwrap();
+    std::fs::write(dir.path().join("003.mp3"), "second").unwrap();
+
+    let job = RenameJob::new(dir.path(), "").padding_zeros(3).in_place(true);
+    let plan = job.plan().unwrap();
+    assert!(plan.check().is_clean());
+
+    let events: Vec<Progress> = plan.execute().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+    assert_eq!(std::fs::read_to_string(dir.path().join("001.mp3")).unwrap(), "first");
+    assert_eq!(std::fs::read_to_string(dir.path().join("002.mp3")).unwrap(), "second");
+    assert!(!dir.path().join("003.mp3").exists());
+}
+
+#[tokio::test]
+async fn test_undo_reverses_in_place_run() {
+    let dir = tempdir().unwrap();
+    let journals = tempdir().unwrap();
+    std::fs::write(dir.path().join("b.mp3"), "b").unwrap();
+    std::fs::write(dir.path().join("a.mp3"), "a").unwrap();
+
+    let events: Vec<Progress> = RenameJob::new(dir.path(), "")
+        .in_place(true)
+        .journal_dir(journals.path())
+        .run()
+        .collect()
+        .await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+    assert!(dir.path().join("01.mp3").exists());
+
+    let journal = renamer::Journal::latest(journals.path()).unwrap().unwrap();
+    let report = journal.undo().unwrap();
+    assert_eq!(report.reverted, 2);
+    assert_eq!(std::fs::read_to_string(dir.path().join("a.mp3")).unwrap(), "a");
+    assert_eq!(std::fs::read_to_string(dir.path().join("b.mp3")).unwrap(), "b");
+    assert!(renamer::Journal::latest(journals.path()).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_cancelled_run_stops_before_next_file() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for i in 1..=3 {
+        File::create(input_dir.path().join(format!("{}.mp3", i))).unwrap();
+    }
+
+    let control = renamer::RunControl::default();
+    let plan = RenameJob::new(input_dir.path(), output_dir.path()).plan().unwrap();
+    let mut stream = Box::pin(plan.execute_with_control(control.clone()));
+
+    assert!(matches!(stream.next().await, Some(Progress::Renamed { done: 1, .. })));
+    control.cancel();
+    let Some(Progress::Cancelled { completed }) = stream.next().await else { panic!("Expected a cancel") };
+    assert_eq!(completed, vec![output_dir.path().join("01.mp3")]);
+    let written = std::fs::read_dir(output_dir.path()).unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name() != renamer::MANIFEST_NAME)
+        .count();
+    assert_eq!(written, 1);
+}
+
+#[tokio::test]
+async fn test_preserve_tree_mirrors_subfolders() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for disc in ["Disc 1", "Disc 2"] {
+        std::fs::create_dir(input_dir.path().join(disc)).unwrap();
+        File::create(input_dir.path().join(disc).join("track.mp3")).unwrap();
+    }
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .padding_zeros(2)
+        .preserve_tree(true)
+        .plan()
+        .unwrap();
+    let events: Vec<Progress> = plan.execute().collect().await;
+
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+    assert!(output_dir.path().join("Disc 1").join("01.mp3").exists());
+    assert!(output_dir.path().join("Disc 2").join("02.mp3").exists());
+}
+
+#[test]
+fn test_several_inputs_are_numbered_as_one_sequence() {
+    let camera = tempdir().unwrap();
+    let phone = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for name in ["a.mp3", "b.mp3"] {
+        File::create(camera.path().join(name)).unwrap();
+    }
+    File::create(phone.path().join("c.mp3")).unwrap();
+
+    let plan = RenameJob::new(camera.path(), output_dir.path())
+        .add_input(phone.path())
+        .input_merge(InputMerge::RoundRobin)
+        .padding_zeros(2)
+        .include_original_name(true)
+        .plan()
+        .unwrap();
+
+    let names: Vec<_> = plan.entries.iter()
+        .map(|entry| entry.destination.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names, ["01_a.mp3", "02_c.mp3", "03_b.mp3"]);
+}
+
+#[test]
+fn test_plan_rejects_a_step_of_zero() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    File::create(input_dir.path().join("a.mp3")).unwrap();
+
+    let result = RenameJob::new(input_dir.path(), output_dir.path())
+        .counter(Counter { step: 0, ..Counter::default() })
+        .plan();
+    assert!(result.unwrap_err().contains("1 or more"));
+}
+
+#[tokio::test]
+async fn test_second_run_skips_nested_output_and_marked_folders() {
+    let input_dir = tempdir().unwrap();
+    for i in 1..=2 {
+        File::create(input_dir.path().join(format!("{}.mp3", i))).unwrap();
+    }
+    let job = RenameJob::new(input_dir.path(), input_dir.path().join("output")).padding_zeros(2);
+
+    let plan = job.plan().unwrap();
+    assert_eq!(plan.warnings.len(), 1);
+    let events: Vec<Progress> = plan.execute().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+
+    // A run into another folder leaves a manifest that keeps it out of later scans too.
+    let elsewhere = input_dir.path().join("copies");
+    let events: Vec<Progress> = RenameJob::new(input_dir.path(), &elsewhere).run().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(files))) if files.len() == 2));
+    assert!(elsewhere.join(renamer::MANIFEST_NAME).exists());
+
+    assert_eq!(job.plan().unwrap().len(), 2);
+}
+
+#[test]
+fn test_plan_never_names_a_path() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    File::create(input_dir.path().join("..mp3")).unwrap();
+    let job = || RenameJob::new(input_dir.path(), output_dir.path());
+
+    assert!(job().template("{mtime:%Y/%m}-{n}{ext}").plan().is_err());
+    assert!(job().template("{mtime:../../%Y}{n}{ext}").plan().is_err());
+    let error = job().template("{stem}").plan().unwrap_err();
+    assert!(error.contains("the name '.'"), "{}", error);
+}
+
+#[tokio::test]
+async fn test_files_that_cannot_take_tags_are_copied_untagged() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    std::fs::write(input_dir.path().join("broken.mp3"), "not audio").unwrap();
+    std::fs::write(input_dir.path().join("other.mp3"), "not audio either").unwrap();
+    let job = RenameJob::new(input_dir.path(), output_dir.path()).write_tags(TagWriting::default());
+
+    let plan = job.plan().unwrap();
+    assert!(plan.entries.iter().all(|e| e.tag_update.is_none()));
+    assert!(plan.entries[0].notes[0].starts_with("tags cannot be written"), "{:?}", plan.entries[0].notes);
+    let events: Vec<Progress> = plan.execute().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(files))) if files.len() == 2));
+
+    assert!(job.transfer_mode(TransferMode::Move).plan().is_err());
+}
+
+#[test]
+fn test_scan_can_be_abandoned_while_reading_metadata() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    File::create(input_dir.path().join("a.mp3")).unwrap();
+    File::create(input_dir.path().join("b.mp3")).unwrap();
+    let job = RenameJob::new(input_dir.path(), output_dir.path())
+        .sort(SortOrder { key: SortKey::Modified, descending: false });
+
+    let mut calls = Vec::new();
+    let result = job.plan_with_progress(|found| {
+        calls.push(found);
+        calls.len() <= 2
+    });
+    assert_eq!(result.unwrap_err(), "Scan cancelled.");
+    assert_eq!(calls, vec![1, 2, 2]);
+}
+
+#[test]
+fn test_plan_transliterates_original_names() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    File::create(input_dir.path().join("Кино - Группа крови.mp3")).unwrap();
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .include_original_name(true)
+        .transliterate(Transliteration::Phonetic)
+        .plan()
+        .unwrap();
+
+    assert_eq!(plan.entries[0].destination, output_dir.path().join("1_Kino - Gruppa krovi.mp3"));
+}
+
+#[test]
+fn test_plan_sanitizes_names_and_folders_for_fat32() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    std::fs::create_dir(input_dir.path().join("Live: 1999")).unwrap();
+    File::create(input_dir.path().join("Live: 1999").join("What?.mp3")).unwrap();
+    File::create(input_dir.path().join("fine.mp3")).unwrap();
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .include_original_name(true)
+        .preserve_tree(true)
+        .fs_profile(FsProfile::Fat32)
+        .plan()
+        .unwrap();
+
+    assert_eq!(plan.entries[0].destination, output_dir.path().join("Live_ 1999").join("01_What_.mp3"));
+    assert_eq!(plan.entries[0].notes, vec![
+        "folder names adjusted for fat32",
+        "name adjusted for fat32: characters the file system rejects replaced",
+    ]);
+    assert_eq!(plan.entries[1].destination, output_dir.path().join("02_fine.mp3"));
+    assert!(plan.entries[1].notes.is_empty());
+}
+
+#[tokio::test]
+async fn test_device_profile_splits_and_shortens_output() {
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    for name in ["Первая песня.mp3", "Вторая песня.mp3", "Третья песня.mp3"] {
+        File::create(input_dir.path().join(name)).unwrap();
+    }
+    let device = DeviceProfile {
+        max_files_per_folder: Some(2),
+        max_depth: Some(1),
+        ascii_only: true,
+        short_names: true,
+        ..DeviceProfile::new("Old player", FsProfile::Fat32)
+    };
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .include_original_name(true)
+        .device(device)
+        .plan()
+        .unwrap();
+    let destinations: Vec<_> = plan.entries.iter()
+        .map(|entry| entry.destination.strip_prefix(output_dir.path()).unwrap().to_path_buf())
+        .collect();
+    assert_eq!(destinations, vec![
+        std::path::PathBuf::from("1/01_VTORA.MP3"),
+        std::path::PathBuf::from("1/02_PERVA.MP3"),
+        std::path::PathBuf::from("2/03_TRETY.MP3"),
+    ]);
+    assert_eq!(plan.warnings.len(), 2);
+    assert!(plan.check().is_clean());
+
+    let events: Vec<Progress> = plan.execute().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+    assert!(output_dir.path().join("2").join("03_TRETY.MP3").exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_non_utf8_names_are_kept_byte_for_byte() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use renamer::file_ops::display_path;
+
+    // "Альбом" and "Привет" in CP1251, as found on old Cyrillic disks.
+    let album = OsStr::from_bytes(b"\xc0\xeb\xfc\xe1\xee\xec");
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    std::fs::create_dir(input_dir.path().join(album)).unwrap();
+    let source = input_dir.path().join(album).join(OsStr::from_bytes(b"\xcf\xf0\xe8\xe2\xe5\xf2.mp3"));
+    std::fs::write(&source, "song").unwrap();
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .template("{parent} {n:02} {stem|upper}{ext}")
+        .plan()
+        .unwrap();
+    let expected = output_dir.path().join(OsStr::from_bytes(b"\xc0\xeb\xfc\xe1\xee\xec 01 \xcf\xf0\xe8\xe2\xe5\xf2.mp3"));
+    assert_eq!(plan.entries[0].source, source);
+    assert_eq!(plan.entries[0].destination, expected);
+    assert!(display_path(&expected).ends_with("(not UTF-8, shown approximately)"));
+
+    let events: Vec<Progress> = plan.execute().collect().await;
+    assert!(matches!(events.last(), Some(Progress::Finished(Ok(_)))));
+    assert_eq!(std::fs::read_to_string(&expected).unwrap(), "song");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_names_come_out_ascii_on_ascii_devices() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // "Привет" in CP1251: no letters can be read from it, so each byte is replaced.
+    let input_dir = tempdir().unwrap();
+    let output_dir = tempdir().unwrap();
+    std::fs::write(input_dir.path().join(OsStr::from_bytes(b"\xcf\xf0\xe8\xe2\xe5\xf2.mp3")), "song").unwrap();
+    let device = DeviceProfile {
+        ascii_only: true,
+        ..DeviceProfile::new("Old player", FsProfile::Fat32)
+    };
+
+    let plan = RenameJob::new(input_dir.path(), output_dir.path())
+        .include_original_name(true)
+        .device(device)
+        .plan()
+        .unwrap();
+    assert_eq!(plan.entries[0].destination, output_dir.path().join("1_______.mp3"));
+}